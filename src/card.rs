@@ -2,10 +2,19 @@
 
 use image::{Rgba, RgbaImage};
 
-use crate::data::Rectangle;
-use crate::edge::{Canny, RectangleInRectangleWindow};
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::ops::Range;
 
-/// Detect whether a playing card is present exactly in the boundary
+use crate::contour::{ContourTracer, Quadrilateral};
+use crate::data::{Point, Rectangle};
+use crate::edge::{
+    AutoThreshold, Canny, CannyBuilder, ColourGradient, EdgeMap, MaskWindow, Morphology, Pyramid,
+    RectangleInRectangleWindow, RectangleWindow, StructuringElement, Window,
+};
+use crate::lines::{Hough, HoughBuilder};
+use crate::warp::{self, Interpolation};
+
+/// Detect whether a playing card is present exactly in the boundary, or anywhere in the frame
 pub struct Detector {
     card_edge_width: usize,
 
@@ -13,8 +22,66 @@ pub struct Detector {
     boundary: Rectangle,
     inner_boundary: Rectangle,
     outer_boundary: Rectangle,
+    coarse: Option<CoarsePass>,
+    gap_closing: Option<(Morphology, StructuringElement)>,
+    search: Option<Search>,
+    rotated: Option<RotatedSides>,
+    corners: Option<[Point; 4]>,
+    rotation: Option<f32>,
+    format: CardFormat,
+    interpolation: Interpolation,
+}
+
+/// Quick look for the card in a downsampled copy of the frame
+struct CoarsePass {
+    pyramid: Pyramid,
+    canny: Canny<RectangleInRectangleWindow>,
+    boundary: Rectangle,
+    inner_boundary: Rectangle,
+    outer_boundary: Rectangle,
+    /// Full resolution detectors for the detection window without the top, bottom, left or
+    /// right strip, used when the coarse pass finds only the other three sides
+    without_side: [Canny<MaskWindow>; 4],
+    /// Side left out of the last full resolution pass
+    left_out: Option<usize>,
 }
 
+/// Fraction of a side the coarse pass must find for the side to be worth refining
+const COARSE_SCORE: f32 = 0.5;
+
+/// Look for a card shaped outline anywhere in the frame
+struct Search {
+    canny: Canny<RectangleWindow>,
+    window: RectangleWindow,
+    tracer: ContourTracer,
+    /// Smallest and largest area of a card as fractions of the frame
+    area: (f32, f32),
+    /// Short sides of a card over its long sides
+    aspect_ratio: f32,
+    aspect_tolerance: f32,
+    max_rotation: Option<f32>,
+}
+
+/// Look for the sides of a card that is not square to the boundary
+struct RotatedSides {
+    hough: Hough,
+    /// Top, bottom, left and right strips of the detection window
+    strips: [RectangleWindow; 4],
+    max_rotation: f32,
+    /// How far from a side an edge can be and still count, half the unwidened detection window
+    tolerance: usize,
+}
+
+/// Side of a card in normal form, `x * cos(angle) + y * sin(angle) = offset`
+#[derive(Clone, Copy)]
+struct Side {
+    angle: f32,
+    offset: f32,
+}
+
+/// Largest distance of an edge pixel from the outline of a card, as a fraction of its perimeter
+const SEARCH_TOLERANCE: f32 = 0.02;
+
 const EDGE_COLOUR: Rgba<u8> = Rgba([0, 0, 0, 254]);
 const MISS_COLOUR: Rgba<u8> = Rgba([255, 0, 0, 255]);
 const HIT_COLOUR: Rgba<u8> = Rgba([0, 255, 0, 255]);
@@ -25,35 +92,103 @@ impl Detector {
         DetectorBuilder::default()
     }
 
-    fn new(
-        width: usize,
-        height: usize,
-        card_edge_width: usize,
-        detection_window_width: usize,
-        low_threshold: f32,
-        high_threshold: f32,
-    ) -> Self {
-        let image_rect = Rectangle::from_dimensions(width, height);
-        let boundary = get_corners(width, height);
-        let outer_boundary = boundary.clamped_grow(detection_window_width / 2, &image_rect);
-        let inner_boundary = boundary.clamped_shrink(detection_window_width / 2, &image_rect);
-
+    fn new(width: usize, height: usize, builder: &DetectorBuilder) -> Self {
+        let format = builder.format.unwrap_or_default();
+        let boundary = guide(
+            width,
+            height,
+            format,
+            builder.margin.unwrap_or(MARGIN),
+            builder.placement.unwrap_or_default(),
+        );
+        // the ends of the sides of a rotated card move away from the boundary
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_precision_loss,
+            clippy::cast_sign_loss
+        )]
+        let tilt = builder.max_rotation.map_or(0, |max_rotation| {
+            let half_side = boundary.width().max(boundary.height()) as f32 / 2.0;
+            (half_side * max_rotation.tan()).ceil() as usize
+        });
+        let tolerance = builder.detection_window_width.unwrap_or(20) / 2;
+        let detection_window_width = 2 * (tolerance + tilt);
+        let (outer_boundary, inner_boundary) =
+            detection_boundaries(width, height, boundary, detection_window_width);
         let window = RectangleInRectangleWindow::new(outer_boundary, inner_boundary);
+        let strips = strips(&outer_boundary, &inner_boundary);
 
-        Detector {
-            card_edge_width,
-
-            canny: Canny::new(
-                width,
-                height,
-                low_threshold,
-                high_threshold,
-                EDGE_COLOUR,
+        let coarse = match builder.coarse_level.unwrap_or(0) {
+            0 => None,
+            level => {
+                let pyramid = Pyramid::new(width, height, level);
+                let (level_width, level_height) = pyramid.level(level).dimensions();
+                let (level_width, level_height) = (level_width as usize, level_height as usize);
+                let boundary = Rectangle([
+                    Pyramid::to_level(level, *boundary.top_left()),
+                    Pyramid::to_level(level, *boundary.bottom_right()),
+                ]);
+                let (outer_boundary, inner_boundary) = detection_boundaries(
+                    level_width,
+                    level_height,
+                    boundary,
+                    (detection_window_width >> level).max(2),
+                );
+                let window = RectangleInRectangleWindow::new(outer_boundary, inner_boundary);
+                Some(CoarsePass {
+                    pyramid,
+                    canny: builder.canny(level_width, level_height, window).build(),
+                    boundary,
+                    outer_boundary,
+                    inner_boundary,
+                    without_side: [0, 1, 2, 3].map(|side| {
+                        let window = without_strip(width, height, &strips, side);
+                        builder.canny(width, height, window).build()
+                    }),
+                    left_out: None,
+                })
+            }
+        };
+
+        let gap_closing = builder
+            .close_gaps
+            .clone()
+            .map(|element| (Morphology::new(width, height, &window), element));
+
+        let search = if builder.search {
+            let window = RectangleWindow::new(Rectangle::from_dimensions(width, height));
+            Some(Search {
+                canny: builder.canny(width, height, window).build(),
                 window,
-            ),
+                tracer: ContourTracer::new(width, height),
+                area: builder.search_area.unwrap_or((0.1, 0.95)),
+                aspect_ratio: format.portrait().aspect_ratio(),
+                aspect_tolerance: builder.aspect_tolerance.unwrap_or(0.1),
+                max_rotation: builder.max_rotation,
+            })
+        } else {
+            None
+        };
+
+        let rotated = builder.max_rotation.map(|max_rotation| {
+            RotatedSides::new(width, height, boundary, strips, max_rotation, tolerance)
+        });
+
+        Detector {
+            card_edge_width: builder.card_edge_width.unwrap_or(3),
+
+            canny: builder.canny(width, height, window).build(),
             boundary,
             outer_boundary,
             inner_boundary,
+            coarse,
+            gap_closing,
+            search,
+            rotated,
+            corners: None,
+            rotation: None,
+            format,
+            interpolation: builder.interpolation.unwrap_or_default(),
         }
     }
 
@@ -67,102 +202,203 @@ impl Detector {
         self.canny.height
     }
 
-    /// Detect if a card is in the boundary
+    /// Hysteresis low and high thresholds used for the last frame
+    #[must_use]
+    pub fn thresholds(&self) -> (f32, f32) {
+        match (&self.search, &self.coarse) {
+            (Some(search), _) => search.canny.thresholds(),
+            (
+                None,
+                Some(CoarsePass {
+                    without_side,
+                    left_out: Some(side),
+                    ..
+                }),
+            ) => without_side[*side].thresholds(),
+            _ => self.canny.thresholds(),
+        }
+    }
+
+    /// Format of the cards detected
+    #[must_use]
+    pub fn format(&self) -> CardFormat {
+        self.format
+    }
+
+    /// Corners of the card found in the last frame, clockwise from the top left
+    ///
+    /// When searching these are the corners of the outline found, when allowing rotation they are
+    /// where the sides found meet, otherwise they are the corners of the boundary.
+    #[must_use]
+    pub fn corners(&self) -> Option<[Point; 4]> {
+        self.corners
+    }
+
+    /// Rotation of the card found in the last frame in radians, clockwise
+    ///
+    /// Always 0 unless searching or allowing rotation with `DetectorBuilder::max_rotation`.
+    #[must_use]
+    pub fn rotation(&self) -> Option<f32> {
+        self.rotation
+    }
+
+    /// Upright image of the card found in the last frame, `width` pixels wide
+    ///
+    /// The card is cut out of `img`, which should be the frame before `detect` drew on it, and its
+    /// perspective is corrected so that it has the aspect ratio of the format. Returns `None` if no
+    /// card was found.
+    ///
+    /// # Panics
+    ///
+    /// If `width` is 0.
+    #[must_use]
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn extract(&self, img: &RgbaImage, width: u32) -> Option<RgbaImage> {
+        let corners = self.corners?;
+        let height = ((width as f32 / self.format.aspect_ratio()).round() as u32).max(1);
+        let corner = |i: usize| [corners[i][0] as f32, corners[i][1] as f32];
+        warp::warp_perspective(
+            img,
+            [corner(0), corner(1), corner(2), corner(3)],
+            width,
+            height,
+            self.interpolation,
+        )
+    }
+
+    /// Detect if a card is in the boundary, or anywhere in the frame when searching
     pub fn detect(&mut self, img: &mut RgbaImage) -> bool {
-        self.canny.detect(img);
+        let found = if let Some(search) = &mut self.search {
+            search.detect(img)
+        } else {
+            self.detect_in_boundary(img)
+        };
+        self.corners = found.map(|(corners, _)| corners);
+        self.rotation = found.map(|(_, rotation)| rotation);
+        found.is_some()
+    }
 
-        let mut scores = vec![];
+    /// Detect if a card is in the boundary, returning its corners and rotation
+    fn detect_in_boundary(&mut self, img: &mut RgbaImage) -> Option<([Point; 4], f32)> {
+        let edges = if let Some(coarse) = &mut self.coarse {
+            coarse.pyramid.update(img);
+            let edges = coarse
+                .pyramid
+                .detect_edges(coarse.pyramid.levels(), &mut coarse.canny);
+            let hits = side_hits(
+                |x, y| edges.edges().is_edge(x, y),
+                &coarse.boundary,
+                &coarse.outer_boundary,
+                &coarse.inner_boundary,
+            );
+            // at least 3 sides are there in part
+            let mut not_found = (0..4).filter(|&side| score(&hits[side]) <= COARSE_SCORE);
+            let left_out = not_found.next();
+            if not_found.next().is_some() {
+                for [x, y] in edges.points() {
+                    img.put_pixel(x as u32, y as u32, EDGE_COLOUR);
+                }
+                let misses = [
+                    vec![false; self.boundary.width()],
+                    vec![false; self.boundary.width()],
+                    vec![false; self.boundary.height()],
+                    vec![false; self.boundary.height()],
+                ];
+                self.draw_sides(img, &misses);
+                return None;
+            }
 
-        // horizontal lines
-        let horizontal_lines = [
-            // top line
-            (
-                self.boundary.top_left()[1] as u32,
-                self.outer_boundary.top_left()[1] as u32,
-                self.inner_boundary.top_left()[1] as u32,
-            ),
-            // bottom line
-            (
-                self.boundary.bottom_right()[1] as u32,
-                self.inner_boundary.bottom_right()[1] as u32,
-                self.outer_boundary.bottom_right()[1] as u32,
-            ),
-        ];
-
-        for (y, min_y, max_y) in horizontal_lines.iter().cloned() {
-            let hits = self
-                .boundary
-                .x_range()
-                .filter(|&x| {
-                    let x = x as u32;
-
-                    let (colour, hit) =
-                        if (min_y..max_y).any(|y| *img.get_pixel(x, y) == EDGE_COLOUR) {
-                            (HIT_COLOUR, true)
-                        } else {
-                            (MISS_COLOUR, false)
-                        };
+            // refine only the strips of the sides found
+            coarse.left_out = left_out;
+            match left_out {
+                Some(side) => coarse.without_side[side].detect_edges(img),
+                None => self.canny.detect_edges(img),
+            }
+        } else {
+            self.canny.detect_edges(img)
+        };
+        let morphology = if let Some((morphology, element)) = &mut self.gap_closing {
+            morphology.load(edges).close(element);
+            morphology.render(img, EDGE_COLOUR);
+            Some(&*morphology)
+        } else {
+            edges.render(img, EDGE_COLOUR);
+            None
+        };
+        let is_edge = |x, y| match morphology {
+            Some(morphology) => morphology.is_edge(x, y),
+            None => edges.is_edge(x, y),
+        };
 
-                    let y_range = if y < self.boundary.height() as u32 / 2 {
-                        y - self.card_edge_width as u32..=y
-                    } else {
-                        y..=y + self.card_edge_width as u32
-                    };
-                    for y in y_range {
-                        img.put_pixel(x, y, colour);
-                    }
-                    hit
-                })
-                .count();
-            scores.push(hits as f32 / self.boundary.width() as f32);
+        if let Some(rotated) = &mut self.rotated {
+            return rotated.detect(img, edges, is_edge, &self.boundary, self.card_edge_width);
+        }
+
+        let hits = side_hits(
+            is_edge,
+            &self.boundary,
+            &self.outer_boundary,
+            &self.inner_boundary,
+        );
+        self.draw_sides(img, &hits);
+
+        // at least 3 sides have scores above 80%
+        if hits.iter().filter(|side| score(side) > 0.8).count() >= 3 {
+            let [left, top] = *self.boundary.top_left();
+            let [right, bottom] = *self.boundary.bottom_right();
+            Some((
+                [[left, top], [right, top], [right, bottom], [left, bottom]],
+                0.0,
+            ))
+        } else {
+            None
         }
+    }
 
-        // vertical lines
-        let vertical_lines = [
-            // left line
+    /// Draw the top, bottom, left and right sides of the boundary showing where edges were found
+    fn draw_sides(&self, img: &mut RgbaImage, hits: &[Vec<bool>; 4]) {
+        let boundary = self.boundary;
+        let card_edge_width = self.card_edge_width as u32;
+        let colour = |hit| if hit { HIT_COLOUR } else { MISS_COLOUR };
+        let (top, bottom) = (
+            boundary.top_left()[1] as u32,
+            boundary.bottom_right()[1] as u32,
+        );
+        let (left, right) = (
+            boundary.top_left()[0] as u32,
+            boundary.bottom_right()[0] as u32,
+        );
+
+        for (y_range, hits) in &[
+            (top.saturating_sub(card_edge_width)..=top, &hits[0]),
             (
-                self.boundary.top_left()[0] as u32,
-                self.outer_boundary.top_left()[0] as u32,
-                self.inner_boundary.top_left()[0] as u32,
+                bottom..=(bottom + card_edge_width).min(img.height() - 1),
+                &hits[1],
             ),
-            // right line
+        ] {
+            for (x, &hit) in boundary.x_range().zip(hits.iter()) {
+                for y in y_range.clone() {
+                    img.put_pixel(x as u32, y, colour(hit));
+                }
+            }
+        }
+        for (x_range, hits) in &[
+            (left.saturating_sub(card_edge_width)..=left, &hits[2]),
             (
-                self.boundary.bottom_right()[0] as u32,
-                self.inner_boundary.bottom_right()[0] as u32,
-                self.outer_boundary.bottom_right()[0] as u32,
+                right..=(right + card_edge_width).min(img.width() - 1),
+                &hits[3],
             ),
-        ];
-
-        for (x, min_x, max_x) in vertical_lines.iter().cloned() {
-            let hits = self
-                .boundary
-                .y_range()
-                .filter(|&y| {
-                    let y = y as u32;
-
-                    let (colour, hit) =
-                        if (min_x..max_x).any(|x| *img.get_pixel(x, y) == EDGE_COLOUR) {
-                            (HIT_COLOUR, true)
-                        } else {
-                            (MISS_COLOUR, true)
-                        };
-
-                    let x_range = if x < self.boundary.width() as u32 / 2 {
-                        x - self.card_edge_width as u32..=x
-                    } else {
-                        x..=x + self.card_edge_width as u32
-                    };
-                    for x in x_range {
-                        img.put_pixel(x, y, colour);
-                    }
-                    hit
-                })
-                .count();
-            scores.push(hits as f32 / self.boundary.height() as f32);
+        ] {
+            for (y, &hit) in boundary.y_range().zip(hits.iter()) {
+                for x in x_range.clone() {
+                    img.put_pixel(x, y as u32, colour(hit));
+                }
+            }
         }
-
-        // at least 3 sides have scores above 80%
-        scores.into_iter().filter(|&s| s > 0.8).count() >= 3
     }
 }
 
@@ -173,6 +409,19 @@ pub struct DetectorBuilder {
     detection_window_width: Option<usize>,
     low_threshold: Option<f32>,
     high_threshold: Option<f32>,
+    blur_sigma: Option<f32>,
+    colour_gradient: Option<ColourGradient>,
+    auto_threshold: Option<AutoThreshold>,
+    coarse_level: Option<usize>,
+    close_gaps: Option<StructuringElement>,
+    search: bool,
+    search_area: Option<(f32, f32)>,
+    aspect_tolerance: Option<f32>,
+    interpolation: Option<Interpolation>,
+    max_rotation: Option<f32>,
+    format: Option<CardFormat>,
+    margin: Option<f32>,
+    placement: Option<Placement>,
 }
 
 impl DetectorBuilder {
@@ -211,36 +460,749 @@ impl DetectorBuilder {
         self
     }
 
+    /// Canny Gaussian blur sigma
+    ///
+    /// The standard deviation of the Gaussian blur applied to the frame before edge detection.
+    /// Noisy frames, such as those from a webcam, benefit from a sigma of around 1.0 to 1.5. A
+    /// value of 0 disables the blur.
+    pub fn blur_sigma(&mut self, value: f32) -> &mut Self {
+        self.blur_sigma = Some(value);
+        self
+    }
+
+    /// Canny colour gradient
+    ///
+    /// How the colour channels are combined when looking for edges. The default only looks at
+    /// brightness, which can miss a card on a background of a similar brightness but different
+    /// colour.
+    pub fn colour_gradient(&mut self, value: ColourGradient) -> &mut Self {
+        self.colour_gradient = Some(value);
+        self
+    }
+
+    /// Canny automatic hysterisis thresholds
+    ///
+    /// Derive the low and high thresholds from each frame rather than using fixed values. This
+    /// copes far better with changes in lighting. When set the low and high thresholds are
+    /// ignored.
+    pub fn auto_threshold(&mut self, value: AutoThreshold) -> &mut Self {
+        self.auto_threshold = Some(value);
+        self
+    }
+
+    /// Pyramid level of a coarse first pass
+    ///
+    /// The card is first looked for in a copy of the frame downsampled by two to the power of
+    /// the level, where the artwork on the card and background has less detail. Frames where
+    /// fewer than three sides are even partly found there are rejected without looking at the
+    /// full resolution frame. Otherwise the full resolution frame is only searched in the strips
+    /// of the detection window around the sides that were found, a side the coarse pass missed
+    /// counts as missing. A value of 0, the default, skips the coarse pass.
+    pub fn coarse_level(&mut self, value: usize) -> &mut Self {
+        self.coarse_level = Some(value);
+        self
+    }
+
+    /// Close the detected edges before the sides are scored
+    ///
+    /// Gaps in the card edges smaller than the structuring element, such as those left by glare
+    /// or fingers, are bridged. The coarse pass, if any, is not closed.
+    pub fn close_gaps(&mut self, element: StructuringElement) -> &mut Self {
+        self.close_gaps = Some(element);
+        self
+    }
+
+    /// Look for the card anywhere in the frame rather than only in the boundary
+    ///
+    /// The card is the largest convex four sided outline in the edges whose area and aspect
+    /// ratio are within the limits set by `search_area` and `aspect_tolerance`. Its corners are
+    /// available from `Detector::corners`. The boundary and coarse pass are not used.
+    pub fn search(&mut self, value: bool) -> &mut Self {
+        self.search = value;
+        self
+    }
+
+    /// Smallest and largest area of a card found by `search`, as fractions of the frame area
+    ///
+    /// Defaults to 0.1 and 0.95.
+    pub fn search_area(&mut self, min: f32, max: f32) -> &mut Self {
+        self.search_area = Some((min, max));
+        self
+    }
+
+    /// Largest difference between the aspect ratio of a card found by `search` and its format
+    ///
+    /// The aspect ratio is the average length of the short sides over that of the long sides, so
+    /// cards are found in either orientation. Defaults to 0.1, which allows for some perspective.
+    pub fn aspect_tolerance(&mut self, value: f32) -> &mut Self {
+        self.aspect_tolerance = Some(value);
+        self
+    }
+
+    /// How `Detector::extract` resamples the card
+    ///
+    /// Defaults to `Interpolation::Bilinear`.
+    pub fn interpolation(&mut self, value: Interpolation) -> &mut Self {
+        self.interpolation = Some(value);
+        self
+    }
+
+    /// Size of the cards to detect
+    ///
+    /// The boundary is the largest card of this format that fits in the frame. Defaults to
+    /// `CardFormat::POKER`, use `CardFormat::landscape` for cards turned on their side.
+    pub fn format(&mut self, value: CardFormat) -> &mut Self {
+        self.format = Some(value);
+        self
+    }
+
+    /// Margin between the edges of the frame and the boundary, as a fraction of the short side of
+    /// the boundary
+    ///
+    /// Defaults to 0.05.
+    ///
+    /// # Panics
+    ///
+    /// If the margin is not at least 0 and less than 0.5.
+    pub fn margin(&mut self, value: f32) -> &mut Self {
+        assert!(
+            (0.0..0.5).contains(&value),
+            "margin must be at least 0 and less than 0.5"
+        );
+        self.margin = Some(value);
+        self
+    }
+
+    /// Where the boundary is placed in the frame
+    ///
+    /// Defaults to `Placement::TopLeft`. The detection window follows the boundary.
+    pub fn placement(&mut self, value: Placement) -> &mut Self {
+        self.placement = Some(value);
+        self
+    }
+
+    /// Accept cards rotated by up to this many radians either way
+    ///
+    /// The detection window is widened so that it holds the sides of a rotated card, and each
+    /// side is found as the strongest straight line in its part of the window. The card is
+    /// detected if at least 3 sides are found along most of their length, and its rotation is
+    /// available from `Detector::rotation`. When searching, cards rotated further are rejected.
+    pub fn max_rotation(&mut self, value: f32) -> &mut Self {
+        self.max_rotation = Some(value);
+        self
+    }
+
     /// Build the Detector
     pub fn build(&self, width: usize, height: usize) -> Detector {
-        Detector::new(
-            width,
-            height,
-            self.card_edge_width.unwrap_or(3),
-            self.detection_window_width.unwrap_or(20),
-            self.low_threshold.unwrap_or(150.0),
-            self.high_threshold.unwrap_or(200.0),
-        )
+        Detector::new(width, height, self)
+    }
+
+    /// Canny edge detector builder for a frame of the given size
+    fn canny<T: Window>(&self, width: usize, height: usize, window: T) -> CannyBuilder<T> {
+        let mut canny = CannyBuilder::with_window(width, height, window);
+        canny
+            .low_threshold(self.low_threshold.unwrap_or(150.0))
+            .high_threshold(self.high_threshold.unwrap_or(200.0))
+            .line_colour(EDGE_COLOUR)
+            .blur_sigma(self.blur_sigma.unwrap_or(0.0))
+            .colour_gradient(self.colour_gradient.unwrap_or_default());
+        if let Some(auto_threshold) = self.auto_threshold {
+            canny.auto_threshold(auto_threshold);
+        }
+        canny
+    }
+}
+
+/// Size of the cards to detect
+///
+/// Only the ratio of the width to the height matters, the presets are in inches. A card is
+/// landscape when it is wider than it is tall.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CardFormat {
+    width: f32,
+    height: f32,
+}
+
+impl CardFormat {
+    /// Poker cards, 2.5 x 3.5 inches
+    pub const POKER: Self = Self {
+        width: 2.5,
+        height: 3.5,
+    };
+    /// Bridge cards, 2.25 x 3.5 inches
+    pub const BRIDGE: Self = Self {
+        width: 2.25,
+        height: 3.5,
+    };
+    /// Tarot cards, 2.75 x 4.75 inches
+    pub const TAROT: Self = Self {
+        width: 2.75,
+        height: 4.75,
+    };
+    /// Mini euro cards, 44 x 68 mm
+    pub const MINI_EURO: Self = Self {
+        width: 1.73,
+        height: 2.68,
+    };
+    /// Square cards
+    pub const SQUARE: Self = Self {
+        width: 2.5,
+        height: 2.5,
+    };
+
+    /// Cards of the given width and height, in any unit
+    ///
+    /// # Panics
+    ///
+    /// If either dimension is not positive.
+    #[must_use]
+    pub fn new(width: f32, height: f32) -> Self {
+        assert!(
+            width > 0.0 && height > 0.0,
+            "card dimensions must be positive"
+        );
+        Self { width, height }
+    }
+
+    /// The same cards turned to be wider than they are tall
+    #[must_use]
+    pub fn landscape(self) -> Self {
+        if self.width < self.height {
+            Self::new(self.height, self.width)
+        } else {
+            self
+        }
+    }
+
+    /// The same cards turned to be taller than they are wide
+    #[must_use]
+    pub fn portrait(self) -> Self {
+        if self.width > self.height {
+            Self::new(self.height, self.width)
+        } else {
+            self
+        }
+    }
+
+    /// Width of the cards
+    #[must_use]
+    pub fn width(self) -> f32 {
+        self.width
+    }
+
+    /// Height of the cards
+    #[must_use]
+    pub fn height(self) -> f32 {
+        self.height
+    }
+
+    /// Width of the cards over their height
+    #[must_use]
+    pub fn aspect_ratio(self) -> f32 {
+        self.width / self.height
+    }
+}
+
+impl Default for CardFormat {
+    fn default() -> Self {
+        Self::POKER
     }
 }
 
-/// ratio taken from standard card dimensions of 2.5 x 3.5
-const RATIO: f32 = 5.0 / 7.0;
+/// Where the boundary is placed in the frame
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum Placement {
+    /// Against the top and left edges of the frame, inside the margin
+    #[default]
+    TopLeft,
+    /// In the middle of the frame
+    Centred,
+    /// Fractions of the space left beside and below the boundary that are to its left and above
+    /// it, 0 against the top or left of the frame and 1 against the bottom or right
+    Anchor {
+        /// Fraction of the space to the left of the boundary
+        x: f32,
+        /// Fraction of the space above the boundary
+        y: f32,
+    },
+    /// Exactly this rectangle, the format and margin are not used
+    Rectangle(Rectangle),
+}
 
 /// 5% margin between edge of frame and corner lines
 const MARGIN: f32 = 0.05;
 
-/// Get the corners of the boundary
-fn get_corners(width: usize, height: usize) -> Rectangle {
+/// Outer and inner edges of the detection window around a boundary
+fn detection_boundaries(
+    width: usize,
+    height: usize,
+    boundary: Rectangle,
+    detection_window_width: usize,
+) -> (Rectangle, Rectangle) {
+    let image_rect = Rectangle::from_dimensions(width, height);
+    (
+        boundary.clamped_grow(detection_window_width / 2, &image_rect),
+        boundary.clamped_shrink(detection_window_width / 2, &image_rect),
+    )
+}
+
+/// Top, bottom, left and right strips between the outer and inner edges of the detection window
+///
+/// The corners are in both of the strips that meet there.
+fn strips(outer: &Rectangle, inner: &Rectangle) -> [Rectangle; 4] {
+    let ([left, top], [right, bottom]) = (*outer.top_left(), *outer.bottom_right());
+    let ([inner_left, inner_top], [inner_right, inner_bottom]) =
+        (*inner.top_left(), *inner.bottom_right());
+    [
+        Rectangle([[left, top], [right, inner_top]]),
+        Rectangle([[left, inner_bottom], [right, bottom]]),
+        Rectangle([[left, top], [inner_left, bottom]]),
+        Rectangle([[inner_right, top], [right, bottom]]),
+    ]
+}
+
+/// Detection window made of every strip but one
+fn without_strip(width: usize, height: usize, strips: &[Rectangle; 4], side: usize) -> MaskWindow {
+    MaskWindow::from_fn(width, height, |x, y| {
+        strips.iter().enumerate().any(|(i, strip)| {
+            i != side && strip.x_range().contains(&x) && strip.y_range().contains(&y)
+        })
+    })
+}
+
+/// Whether an edge crosses the detection window at each pixel along the top, bottom, left and
+/// right sides of the boundary
+fn side_hits<F: Fn(usize, usize) -> bool>(
+    is_edge: F,
+    boundary: &Rectangle,
+    outer: &Rectangle,
+    inner: &Rectangle,
+) -> [Vec<bool>; 4] {
+    let horizontal = |y_range: Range<usize>| {
+        boundary
+            .x_range()
+            .map(|x| y_range.clone().any(|y| is_edge(x, y)))
+            .collect()
+    };
+    let vertical = |x_range: Range<usize>| {
+        boundary
+            .y_range()
+            .map(|y| x_range.clone().any(|x| is_edge(x, y)))
+            .collect()
+    };
+    [
+        horizontal(outer.top_left()[1]..inner.top_left()[1]),
+        horizontal(inner.bottom_right()[1]..outer.bottom_right()[1]),
+        vertical(outer.top_left()[0]..inner.top_left()[0]),
+        vertical(inner.bottom_right()[0]..outer.bottom_right()[0]),
+    ]
+}
+
+/// Fraction of a side where edges were found
+fn score(hits: &[bool]) -> f32 {
+    hits.iter().filter(|&&hit| hit).count() as f32 / hits.len() as f32
+}
+
+impl Search {
+    /// Find the card in the frame, drawing the edges and its outline
+    fn detect(&mut self, img: &mut RgbaImage) -> Option<([Point; 4], f32)> {
+        let edges = self.canny.detect_edges(img);
+        #[allow(clippy::cast_precision_loss)]
+        let frame = (edges.width() * edges.height()) as f32;
+        let (area, ratio, aspect_tolerance, max_rotation) = (
+            self.area,
+            self.aspect_ratio,
+            self.aspect_tolerance,
+            self.max_rotation,
+        );
+        let found = self
+            .tracer
+            .quadrilaterals(edges, &self.window, SEARCH_TOLERANCE)
+            .into_iter()
+            .filter(|quadrilateral| {
+                quadrilateral.convex
+                    && (area.0..=area.1).contains(&(quadrilateral.area / frame))
+                    && (aspect_ratio(quadrilateral) - ratio).abs() <= aspect_tolerance
+            })
+            .map(|quadrilateral| {
+                let corners = clockwise(quadrilateral.corners);
+                (corners, rotation_of(&corners))
+            })
+            .find(|(_, rotation)| max_rotation.is_none_or(|max| rotation.abs() <= max));
+
+        edges.render(img, EDGE_COLOUR);
+        if let Some((corners, _)) = found {
+            for i in 0..4 {
+                draw_line(img, corners[i], corners[(i + 1) % 4], HIT_COLOUR);
+            }
+        }
+        found
+    }
+}
+
+impl RotatedSides {
+    /// Look for the sides in the strips of the detection window
+    #[allow(clippy::cast_possible_truncation)]
+    fn new(
+        width: usize,
+        height: usize,
+        boundary: Rectangle,
+        strips: [Rectangle; 4],
+        max_rotation: f32,
+        tolerance: usize,
+    ) -> Self {
+        Self {
+            hough: HoughBuilder::new(width, height)
+                .angle_resolution(PI / 360.0)
+                .threshold((boundary.width().min(boundary.height()) / 3) as u32)
+                .build(),
+            strips: strips.map(RectangleWindow::new),
+            max_rotation,
+            tolerance,
+        }
+    }
+
+    /// Find the sides of the card, drawing where edges were found along them
+    fn detect<F: Fn(usize, usize) -> bool>(
+        &mut self,
+        img: &mut RgbaImage,
+        edges: &EdgeMap,
+        is_edge: F,
+        boundary: &Rectangle,
+        card_edge_width: usize,
+    ) -> Option<([Point; 4], f32)> {
+        // the strongest line in each strip close enough to the side of the boundary, with its
+        // rotation and votes
+        let max_rotation = self.max_rotation;
+        let mut found = [None; 4];
+        for (i, (strip, found)) in self.strips.iter().zip(&mut found).enumerate() {
+            *found = self
+                .hough
+                .lines(edges, strip)
+                .into_iter()
+                .map(|line| {
+                    let side = Side {
+                        angle: line.angle,
+                        offset: line.offset,
+                    };
+                    let side = if i < 2 { side } else { side.upright() };
+                    (side, side.rotation(i), line.votes)
+                })
+                .find(|&(_, rotation, _)| rotation.abs() <= max_rotation);
+        }
+
+        let tolerance = self.tolerance;
+
+        // sides that were not found are the side of the boundary, rotated with the others
+        #[allow(clippy::cast_precision_loss)]
+        let rotation = {
+            let votes: u32 = found.iter().flatten().map(|&(_, _, votes)| votes).sum();
+            let total: f32 = found
+                .iter()
+                .flatten()
+                .map(|&(_, rotation, votes)| rotation * votes as f32)
+                .sum();
+            if votes == 0 {
+                0.0
+            } else {
+                total / votes as f32
+            }
+        };
+        let mut sides = [Side {
+            angle: 0.0,
+            offset: 0.0,
+        }; 4];
+        for (i, side) in sides.iter_mut().enumerate() {
+            *side = match found[i] {
+                Some((side, _, _)) => side,
+                None => Side::boundary(boundary, i, rotation),
+            };
+        }
+
+        let mut hits = [vec![], vec![], vec![], vec![]];
+        for (i, hits) in hits.iter_mut().enumerate() {
+            let range = if i < 2 {
+                boundary.x_range()
+            } else {
+                boundary.y_range()
+            };
+            *hits = range
+                .map(|along| {
+                    let [x, y] = sides[i].at(i, along);
+                    (0..=2 * tolerance).any(|across| {
+                        #[allow(clippy::cast_precision_loss)]
+                        let across = across as f32 - tolerance as f32;
+                        let [x, y] = if i < 2 {
+                            [x, y + across]
+                        } else {
+                            [x + across, y]
+                        };
+                        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                        let (px, py) = (x.round() as usize, y.round() as usize);
+                        x >= 0.0 && y >= 0.0 && is_edge(px, py)
+                    })
+                })
+                .collect();
+        }
+        draw_rotated_sides(img, boundary, &sides, &hits, card_edge_width);
+
+        // at least 3 sides have scores above 80%
+        if hits.iter().filter(|side| score(side) > 0.8).count() < 3 {
+            return None;
+        }
+        let corner = |a: usize, b: usize| sides[a].intersection(sides[b]);
+        Some((
+            [corner(0, 2), corner(0, 3), corner(1, 3), corner(1, 2)],
+            rotation,
+        ))
+    }
+}
+
+impl Side {
+    /// Side `i` of the boundary, top, bottom, left or right, rotated about its middle
+    #[allow(clippy::cast_precision_loss)]
+    fn boundary(boundary: &Rectangle, i: usize, rotation: f32) -> Self {
+        let [left, top] = *boundary.top_left();
+        let [right, bottom] = *boundary.bottom_right();
+        let (middle, angle) = match i {
+            0 => (
+                [(left + right) as f32 / 2.0, top as f32],
+                FRAC_PI_2 + rotation,
+            ),
+            1 => (
+                [(left + right) as f32 / 2.0, bottom as f32],
+                FRAC_PI_2 + rotation,
+            ),
+            2 => ([left as f32, (top + bottom) as f32 / 2.0], rotation),
+            _ => ([right as f32, (top + bottom) as f32 / 2.0], rotation),
+        };
+        Self {
+            angle,
+            offset: middle[0] * angle.cos() + middle[1] * angle.sin(),
+        }
+    }
+
+    /// The same line with its normal pointing right rather than left
+    fn upright(self) -> Self {
+        if self.angle > FRAC_PI_2 {
+            Self {
+                angle: self.angle - PI,
+                offset: -self.offset,
+            }
+        } else {
+            self
+        }
+    }
+
+    /// Clockwise rotation of side `i` from square to the boundary
+    fn rotation(self, i: usize) -> f32 {
+        if i < 2 {
+            self.angle - FRAC_PI_2
+        } else {
+            self.angle
+        }
+    }
+
+    /// Position on side `i` at `along` pixels across a top or bottom side, or down a left or
+    /// right side
+    #[allow(clippy::cast_precision_loss)]
+    fn at(self, i: usize, along: usize) -> [f32; 2] {
+        let along = along as f32;
+        let (cos, sin) = (self.angle.cos(), self.angle.sin());
+        if i < 2 {
+            [along, (self.offset - along * cos) / sin]
+        } else {
+            [(self.offset - along * sin) / cos, along]
+        }
+    }
+
+    /// The pixel where two sides meet
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn intersection(self, other: Self) -> Point {
+        let (cos_a, sin_a) = (self.angle.cos(), self.angle.sin());
+        let (cos_b, sin_b) = (other.angle.cos(), other.angle.sin());
+        let determinant = cos_a * sin_b - sin_a * cos_b;
+        let x = (self.offset * sin_b - other.offset * sin_a) / determinant;
+        let y = (cos_a * other.offset - cos_b * self.offset) / determinant;
+        [x.round().max(0.0) as usize, y.round().max(0.0) as usize]
+    }
+}
+
+/// Draw the sides of a rotated card showing where edges were found along them
+fn draw_rotated_sides(
+    img: &mut RgbaImage,
+    boundary: &Rectangle,
+    sides: &[Side; 4],
+    hits: &[Vec<bool>; 4],
+    card_edge_width: usize,
+) {
+    let colour = |hit| if hit { HIT_COLOUR } else { MISS_COLOUR };
+    for (i, hits) in hits.iter().enumerate() {
+        let range = if i < 2 {
+            boundary.x_range()
+        } else {
+            boundary.y_range()
+        };
+        for (along, &hit) in range.zip(hits) {
+            let [x, y] = sides[i].at(i, along);
+            // outwards from the card
+            #[allow(clippy::cast_precision_loss)]
+            for out in 0..=card_edge_width {
+                let out = out as f32;
+                let [x, y] = match i {
+                    0 => [x, y - out],
+                    1 => [x, y + out],
+                    2 => [x - out, y],
+                    _ => [x + out, y],
+                };
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let (px, py) = (x.round() as u32, y.round() as u32);
+                if x >= 0.0 && y >= 0.0 && px < img.width() && py < img.height() {
+                    img.put_pixel(px, py, colour(hit));
+                }
+            }
+        }
+    }
+}
+
+/// Clockwise rotation of a quadrilateral, the average over its sides
+fn rotation_of(corners: &[Point; 4]) -> f32 {
+    #[allow(clippy::cast_precision_loss)]
+    let direction = |a: Point, b: Point| [b[0] as f32 - a[0] as f32, b[1] as f32 - a[1] as f32];
+    let [top, right, bottom, left] = [
+        direction(corners[0], corners[1]),
+        direction(corners[1], corners[2]),
+        direction(corners[3], corners[2]),
+        direction(corners[0], corners[3]),
+    ];
+    (top[1].atan2(top[0])
+        + bottom[1].atan2(bottom[0])
+        + (-right[0]).atan2(right[1])
+        + (-left[0]).atan2(left[1]))
+        / 4.0
+}
+
+/// Average length of the short sides of a quadrilateral over that of the long sides
+fn aspect_ratio(quadrilateral: &Quadrilateral) -> f32 {
+    let corners = quadrilateral.corners;
+    let side = |i: usize| {
+        let (a, b) = (corners[i], corners[(i + 1) % 4]);
+        #[allow(clippy::cast_precision_loss)]
+        (a[0] as f32 - b[0] as f32).hypot(a[1] as f32 - b[1] as f32)
+    };
+    let (a, b) = (side(0) + side(2), side(1) + side(3));
+    a.min(b) / a.max(b)
+}
+
+/// Order the corners of a convex quadrilateral clockwise, starting with the top left
+#[allow(clippy::cast_precision_loss)]
+fn clockwise(mut corners: [Point; 4]) -> [Point; 4] {
+    // with y down the image a positive area means the corners go clockwise
+    let twice_area: f32 = (0..4)
+        .map(|i| {
+            let (a, b) = (corners[i], corners[(i + 1) % 4]);
+            a[0] as f32 * b[1] as f32 - b[0] as f32 * a[1] as f32
+        })
+        .sum();
+    if twice_area < 0.0 {
+        corners.reverse();
+    }
+    let top_left = (0..4)
+        .min_by_key(|&i| corners[i][0] + corners[i][1])
+        .unwrap_or(0);
+    corners.rotate_left(top_left);
+    corners
+}
+
+/// Draw a one pixel wide straight line between two points
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn draw_line(img: &mut RgbaImage, from: Point, to: Point, colour: Rgba<u8>) {
+    let (dx, dy) = (to[0] as f32 - from[0] as f32, to[1] as f32 - from[1] as f32);
+    let steps = dx.abs().max(dy.abs()).max(1.0);
+    for step in 0..=steps as usize {
+        let t = step as f32 / steps;
+        let (x, y) = (
+            (from[0] as f32 + dx * t).round() as u32,
+            (from[1] as f32 + dy * t).round() as u32,
+        );
+        if x < img.width() && y < img.height() {
+            img.put_pixel(x, y, colour);
+        }
+    }
+}
+
+/// The boundary, placed in the frame
+///
+/// # Panics
+///
+/// If an explicit rectangle is empty or not inside the frame, or an anchor is outside 0 to 1.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn guide(
+    width: usize,
+    height: usize,
+    format: CardFormat,
+    margin: f32,
+    placement: Placement,
+) -> Rectangle {
+    let (x, y) = match placement {
+        Placement::TopLeft => (0.0, 0.0),
+        Placement::Centred => (0.5, 0.5),
+        Placement::Anchor { x, y } => {
+            assert!(
+                (0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y),
+                "anchor must be between 0 and 1"
+            );
+            (x, y)
+        }
+        Placement::Rectangle(rectangle) => {
+            let rectangle = Rectangle::new(*rectangle.top_left(), *rectangle.bottom_right());
+            assert!(
+                rectangle.bottom_right()[0] < width && rectangle.bottom_right()[1] < height,
+                "boundary must be inside the frame"
+            );
+            return rectangle;
+        }
+    };
+    let boundary = get_corners(width, height, format, margin);
+    // the boundary is the same distance inside its margin on every side
+    let inset = *boundary.top_left();
+    let space = [
+        width - boundary.bottom_right()[0] - inset[0],
+        height - boundary.bottom_right()[1] - inset[1],
+    ];
+    let shift = [
+        (space[0] as f32 * x).round() as usize,
+        (space[1] as f32 * y).round() as usize,
+    ];
+    Rectangle([
+        [inset[0] + shift[0], inset[1] + shift[1]],
+        [
+            boundary.bottom_right()[0] + shift[0],
+            boundary.bottom_right()[1] + shift[1],
+        ],
+    ])
+}
+
+/// Get the corners of the boundary, the largest card of the format that fits in the frame
+fn get_corners(width: usize, height: usize, format: CardFormat, margin: f32) -> Rectangle {
     let height = height as f32;
     let width = width as f32;
+    let ratio = format.aspect_ratio();
 
-    let (width, height) = if width / height > RATIO {
-        (height * RATIO, height)
+    let (width, height) = if width / height > ratio {
+        (height * ratio, height)
     } else {
-        (width, width / RATIO)
+        (width, width / ratio)
     };
-    let margin = (width * MARGIN).floor() as usize;
+    let margin = (width.min(height) * margin).floor() as usize;
     Rectangle([
         [margin, margin],
         [
@@ -256,20 +1218,129 @@ mod tests {
 
     use test::Bencher;
     use crate::data::Rectangle;
+    use crate::edge::{AutoThreshold, ColourGradient, StructuringElement};
+    use crate::warp::Interpolation;
 
-    use super::{Detector, get_corners};
+    use image::{Rgba, RgbaImage};
+
+    use super::{
+        get_corners, guide, CardFormat, Detector, Placement, EDGE_COLOUR, HIT_COLOUR, MARGIN,
+        MISS_COLOUR,
+    };
 
     #[test]
     fn test_get_corners() {
         let test_box = Rectangle([[2, 2], [38, 54]]);
 
-        assert_eq!(get_corners(40, 56), test_box);
+        assert_eq!(get_corners(40, 56, CardFormat::POKER, MARGIN), test_box);
         // smallest edge used matches test_box
-        assert_eq!(get_corners(49, 56), test_box);
-        assert_eq!(get_corners(40, 59), test_box);
+        assert_eq!(get_corners(49, 56, CardFormat::POKER, MARGIN), test_box);
+        assert_eq!(get_corners(40, 59, CardFormat::POKER, MARGIN), test_box);
         // smallest edge used does not match test_box
-        assert_ne!(get_corners(39, 56), test_box);
-        assert_ne!(get_corners(40, 54), test_box);
+        assert_ne!(get_corners(39, 56, CardFormat::POKER, MARGIN), test_box);
+        assert_ne!(get_corners(40, 54, CardFormat::POKER, MARGIN), test_box);
+    }
+
+    #[test]
+    fn test_card_format() {
+        let poker = CardFormat::default();
+        assert_eq!(poker, CardFormat::POKER);
+        assert!((poker.aspect_ratio() - 5.0 / 7.0).abs() < 1e-6);
+        let landscape = poker.landscape();
+        assert_eq!((landscape.width(), landscape.height()), (3.5, 2.5));
+        assert_eq!(landscape.landscape(), landscape);
+        assert_eq!(landscape.portrait(), poker);
+        assert_eq!(CardFormat::SQUARE.landscape(), CardFormat::SQUARE);
+        assert_eq!(
+            CardFormat::new(3.0, 2.0).portrait(),
+            CardFormat::new(2.0, 3.0)
+        );
+        assert!(CardFormat::TAROT.aspect_ratio() < CardFormat::BRIDGE.aspect_ratio());
+        assert!(CardFormat::BRIDGE.aspect_ratio() < CardFormat::POKER.aspect_ratio());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_card_format_empty() {
+        let _ = CardFormat::new(0.0, 3.0);
+    }
+
+    #[test]
+    fn test_get_corners_format() {
+        assert_eq!(
+            get_corners(560, 400, CardFormat::POKER.landscape(), MARGIN),
+            Rectangle([[20, 20], [540, 380]])
+        );
+        assert_eq!(
+            get_corners(400, 300, CardFormat::SQUARE, MARGIN),
+            Rectangle([[15, 15], [285, 285]])
+        );
+        assert_eq!(
+            get_corners(400, 300, CardFormat::SQUARE, 0.1),
+            Rectangle([[30, 30], [270, 270]])
+        );
+    }
+
+    #[test]
+    fn test_guide() {
+        let landscape = |placement| guide(400, 200, CardFormat::POKER, MARGIN, placement);
+        assert_eq!(
+            landscape(Placement::TopLeft),
+            get_corners(400, 200, CardFormat::POKER, MARGIN)
+        );
+        assert_eq!(
+            landscape(Placement::TopLeft),
+            Rectangle([[7, 7], [135, 193]])
+        );
+        // the same space either side
+        assert_eq!(
+            landscape(Placement::Centred),
+            Rectangle([[136, 7], [264, 193]])
+        );
+        assert_eq!(
+            landscape(Placement::Anchor { x: 1.0, y: 0.0 }),
+            Rectangle([[265, 7], [393, 193]])
+        );
+        let rectangle = Rectangle([[50, 20], [150, 160]]);
+        assert_eq!(landscape(Placement::Rectangle(rectangle)), rectangle);
+
+        // portrait frames have space below the boundary
+        assert_eq!(
+            guide(400, 800, CardFormat::POKER, MARGIN, Placement::Centred),
+            Rectangle([[20, 140], [380, 660]])
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_guide_outside() {
+        let rectangle = Rectangle([[50, 20], [150, 200]]);
+        let _ = guide(
+            400,
+            200,
+            CardFormat::POKER,
+            MARGIN,
+            Placement::Rectangle(rectangle),
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_guide_inverted() {
+        let rectangle = Rectangle([[150, 20], [50, 160]]);
+        let _ = guide(
+            400,
+            200,
+            CardFormat::POKER,
+            MARGIN,
+            Placement::Rectangle(rectangle),
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_margin_too_large() {
+        let _ = Detector::builder().margin(0.5);
     }
 
     #[test]
@@ -285,6 +1356,477 @@ mod tests {
         img.save("test_images/uno-7-save.jpg").unwrap();
     }
 
+    #[test]
+    fn test_detect_vertical_sides() {
+        let (width, height) = (200, 160);
+        let mut detector = Detector::builder()
+            .card_edge_width(0)
+            .detection_window_width(10)
+            .build(width as usize, height as usize);
+        let [left, top] = *detector.boundary.top_left();
+        let [_, bottom] = *detector.boundary.bottom_right();
+        // a light band across the whole frame only has the top and bottom sides of the guide
+        let band = |left: usize| {
+            RgbaImage::from_fn(width, height, |x, y| {
+                if (top..=bottom).contains(&(y as usize)) && x as usize >= left {
+                    Rgba([230, 220, 200, 255])
+                } else {
+                    Rgba([40, 60, 50, 255])
+                }
+            })
+        };
+
+        // missing left and right sides count as misses
+        assert!(!detector.detect(&mut band(0)));
+        // the left side makes three out of four
+        assert!(detector.detect(&mut band(left)));
+    }
+
+    #[test]
+    fn test_detect_blurred() {
+        let mut img = image::open("test_images/uno-7.jpg").unwrap().to_rgba();
+        let mut detector = Detector::builder()
+            .card_edge_width(0)
+            .detection_window_width(20)
+            .blur_sigma(1.4)
+            .low_threshold(50.0)
+            .high_threshold(100.0)
+            .build(img.width() as usize, img.height() as usize);
+
+        assert!(detector.detect(&mut img));
+    }
+
+    #[test]
+    fn test_detect_auto_threshold() {
+        let img = image::open("test_images/uno-7.jpg").unwrap().to_rgba();
+        for &auto_threshold in &[
+            AutoThreshold::Median { low: 1.0, high: 2.0 },
+            AutoThreshold::Otsu { ratio: 0.5 },
+        ] {
+            let mut img = img.clone();
+            let mut detector = Detector::builder()
+                .card_edge_width(0)
+                .detection_window_width(20)
+                .auto_threshold(auto_threshold)
+                .build(img.width() as usize, img.height() as usize);
+
+            assert!(detector.detect(&mut img));
+            let (low, high) = detector.thresholds();
+            assert!(low > 0.0 && low < high);
+        }
+    }
+
+    #[test]
+    fn test_detect_colour_gradient() {
+        let img = image::open("test_images/uno-7.jpg").unwrap().to_rgba();
+        for &colour_gradient in &[ColourGradient::MaxChannel, ColourGradient::DiZenzo] {
+            let mut img = img.clone();
+            let mut detector = Detector::builder()
+                .card_edge_width(0)
+                .detection_window_width(20)
+                .colour_gradient(colour_gradient)
+                .build(img.width() as usize, img.height() as usize);
+
+            assert!(detector.detect(&mut img), "{:?}", colour_gradient);
+        }
+    }
+
+    #[test]
+    fn test_detect_coarse() {
+        let img = image::open("test_images/uno-7.jpg").unwrap().to_rgba();
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let mut detector = Detector::builder()
+            .card_edge_width(0)
+            .detection_window_width(20)
+            .coarse_level(1)
+            .build(width, height);
+        assert!(detector.detect(&mut img.clone()));
+
+        // a frame without a card is rejected by the coarse pass
+        let mut img = RgbaImage::from_pixel(img.width(), img.height(), Rgba([90, 120, 60, 255]));
+        let mut detector = Detector::builder()
+            .card_edge_width(0)
+            .coarse_level(1)
+            .build(width, height);
+        assert!(!detector.detect(&mut img));
+        let boundary = get_corners(width, height, CardFormat::POKER, MARGIN);
+        let [x, y] = *boundary.top_left();
+        assert_eq!(img.get_pixel(x as u32 + 10, y as u32), &MISS_COLOUR);
+    }
+
+    #[test]
+    fn test_detect_coarse_refined() {
+        let (width, height) = (240, 320);
+        let boundary = get_corners(240, 320, CardFormat::POKER, MARGIN);
+        let [left, top] = *boundary.top_left();
+        let [right, bottom] = *boundary.bottom_right();
+        // the card runs off the bottom of the frame, past a short mark under the guide
+        let mut img = card(width, height, Rectangle([[left, top], [right, 319]]));
+        let mark = (115..125, bottom - 3..bottom + 3);
+        for x in mark.0.clone() {
+            for y in mark.1.clone() {
+                img.put_pixel(x as u32, y as u32, Rgba([40, 60, 50, 255]));
+            }
+        }
+        let marked = |img: &RgbaImage| {
+            mark.0.clone().any(|x| {
+                mark.1
+                    .clone()
+                    .any(|y| img.get_pixel(x as u32, y as u32) == &EDGE_COLOUR)
+            })
+        };
+
+        let mut builder = Detector::builder();
+        builder.card_edge_width(0).detection_window_width(10);
+        let mut full = img.clone();
+        assert!(builder.build(240, 320).detect(&mut full));
+        assert!(marked(&full));
+
+        // the coarse pass misses the bottom side, so its strip is not searched at full resolution
+        let mut detector = builder.coarse_level(1).build(240, 320);
+        let mut refined = img.clone();
+        assert!(detector.detect(&mut refined));
+        assert_eq!(detector.coarse.as_ref().unwrap().left_out, Some(1));
+        assert!(!marked(&refined));
+        assert_eq!(refined.get_pixel(120, bottom as u32), &MISS_COLOUR);
+        assert_eq!(refined.get_pixel(120, top as u32), &HIT_COLOUR);
+    }
+
+    #[test]
+    fn test_detect_close_gaps() {
+        let mut img = image::open("test_images/uno-7.jpg").unwrap().to_rgba();
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        // glare breaks every side of the card into short pieces
+        let boundary = get_corners(width, height, CardFormat::POKER, MARGIN);
+        let glare = |x: usize, y: usize| {
+            let near = |a: usize, b: usize| a.max(b) - a.min(b) <= 12;
+            let (top, bottom) = (boundary.top_left()[1], boundary.bottom_right()[1]);
+            let (left, right) = (boundary.top_left()[0], boundary.bottom_right()[0]);
+            ((near(y, top) || near(y, bottom)) && x % 12 < 5)
+                || ((near(x, left) || near(x, right)) && y % 12 < 5)
+        };
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            if glare(x as usize, y as usize) {
+                *pixel = Rgba([255, 255, 255, 255]);
+            }
+        }
+
+        let mut builder = Detector::builder();
+        builder.card_edge_width(0).detection_window_width(20);
+        assert!(!builder.build(width, height).detect(&mut img.clone()));
+        builder.close_gaps(StructuringElement::rectangle(7, 7));
+        assert!(builder.build(width, height).detect(&mut img));
+    }
+
+    /// Light card shaped rectangle on a dark background
+    ///
+    /// The card has a one pixel border between the two to avoid ties in the gradient.
+    fn card(width: u32, height: u32, card: Rectangle) -> RgbaImage {
+        let [left, top] = *card.top_left();
+        let [right, bottom] = *card.bottom_right();
+        RgbaImage::from_fn(width, height, |x, y| {
+            let (x, y) = (x as usize, y as usize);
+            if (left + 1..right).contains(&x) && (top + 1..bottom).contains(&y) {
+                Rgba([230, 220, 200, 255])
+            } else if (left..=right).contains(&x) && (top..=bottom).contains(&y) {
+                Rgba([135, 140, 125, 255])
+            } else {
+                Rgba([40, 60, 50, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn test_detect_search() {
+        let (width, height) = (200, 160);
+        // 70x98 card well away from the centre of the frame
+        let mut img = card(width, height, Rectangle([[20, 40], [89, 137]]));
+        let mut builder = Detector::builder();
+        builder.card_edge_width(0).detection_window_width(20);
+        assert!(!builder.build(200, 160).detect(&mut img.clone()));
+        assert_eq!(builder.build(200, 160).corners(), None);
+
+        let mut detector = builder.search(true).build(200, 160);
+        assert!(detector.detect(&mut img));
+        let corners = detector.corners().unwrap();
+        let expected = [[20, 40], [89, 40], [89, 137], [20, 137]];
+        for (corner, expected) in corners.iter().zip(&expected) {
+            let near = |a: usize, b: usize| a.max(b) - a.min(b) <= 2;
+            assert!(
+                near(corner[0], expected[0]) && near(corner[1], expected[1]),
+                "{:?}",
+                corners
+            );
+        }
+        assert_eq!(img.get_pixel(50, corners[0][1] as u32), &HIT_COLOUR);
+    }
+
+    #[test]
+    fn test_detect_search_rejected() {
+        let mut builder = Detector::builder();
+        builder.search(true);
+        // square
+        let mut img = card(200, 160, Rectangle([[20, 20], [119, 119]]));
+        assert!(!builder.build(200, 160).detect(&mut img));
+        // too small
+        let mut img = card(200, 160, Rectangle([[20, 20], [39, 47]]));
+        let mut detector = builder.build(200, 160);
+        assert!(!detector.detect(&mut img.clone()));
+        assert_eq!(detector.corners(), None);
+        builder.search_area(0.01, 0.95);
+        assert!(builder.build(200, 160).detect(&mut img));
+    }
+
+    #[test]
+    fn test_detect_search_photo() {
+        let mut img = image::open("test_images/uno-7.jpg").unwrap().to_rgba();
+        let mut detector = Detector::builder()
+            .search(true)
+            .blur_sigma(1.4)
+            .low_threshold(50.0)
+            .high_threshold(100.0)
+            .build(img.width() as usize, img.height() as usize);
+        assert!(detector.detect(&mut img));
+        // the card fills most of the photo
+        let [top_left, _, bottom_right, _] = detector.corners().unwrap();
+        assert!(top_left[0] < 20 && top_left[1] < 20, "{:?}", top_left);
+        assert!(
+            bottom_right[0] > 100 && bottom_right[1] > 160,
+            "{:?}",
+            bottom_right
+        );
+    }
+
+    #[test]
+    fn test_extract() {
+        let (width, height) = (200, 160);
+        let img = card(width, height, Rectangle([[20, 40], [89, 137]]));
+        let mut detector = Detector::builder().search(true).build(200, 160);
+        assert_eq!(detector.extract(&img, 50), None);
+        assert!(detector.detect(&mut img.clone()));
+
+        for &interpolation in &[Interpolation::Bilinear, Interpolation::Bicubic] {
+            let mut builder = Detector::builder();
+            let mut detector = builder
+                .search(true)
+                .interpolation(interpolation)
+                .build(200, 160);
+            detector.detect(&mut img.clone());
+            let extracted = detector.extract(&img, 50).unwrap();
+            assert_eq!(extracted.dimensions(), (50, 70));
+            // only the card is left
+            let card = extracted
+                .pixels()
+                .filter(|&pixel| pixel == &Rgba([230, 220, 200, 255]))
+                .count();
+            assert!(card * 5 > 50 * 70 * 4, "{:?} {}", interpolation, card);
+            assert!(extracted.pixels().all(|pixel| pixel[0] > 100));
+        }
+    }
+
+    /// Light card filling the boundary rotated clockwise about its centre, with smoothed edges
+    fn rotated_card(width: u32, height: u32, angle: f32) -> RgbaImage {
+        let boundary = get_corners(width as usize, height as usize, CardFormat::POKER, MARGIN);
+        let [left, top] = boundary.top_left().map(|a| a as f32);
+        let [right, bottom] = boundary.bottom_right().map(|a| a as f32);
+        let centre = [(left + right) / 2.0, (top + bottom) / 2.0];
+        let (cos, sin) = (angle.cos(), angle.sin());
+        RgbaImage::from_fn(width, height, |x, y| {
+            // fraction of 4x4 samples inside the card, rotated back to the boundary
+            let mut inside = 0.0;
+            for sample in 0..16 {
+                let dx = x as f32 + (sample % 4) as f32 / 4.0 - 0.375 - centre[0];
+                let dy = y as f32 + (sample / 4) as f32 / 4.0 - 0.375 - centre[1];
+                let (x, y) = (
+                    centre[0] + dx * cos + dy * sin,
+                    centre[1] - dx * sin + dy * cos,
+                );
+                if (left..=right).contains(&x) && (top..=bottom).contains(&y) {
+                    inside += 1.0 / 16.0;
+                }
+            }
+            let mix = |dark: f32, light: f32| (dark + (light - dark) * inside).round() as u8;
+            Rgba([mix(40.0, 230.0), mix(60.0, 220.0), mix(50.0, 200.0), 255])
+        })
+    }
+
+    #[test]
+    fn test_detect_rotated() {
+        let (width, height) = (300, 320);
+        let boundary = get_corners(300, 320, CardFormat::POKER, MARGIN);
+        let [left, top] = boundary.top_left().map(|a| a as f32);
+        let [right, bottom] = boundary.bottom_right().map(|a| a as f32);
+        let centre = [(left + right) / 2.0, (top + bottom) / 2.0];
+        for &degrees in &[4.0_f32, -4.0, 0.0] {
+            let angle = degrees.to_radians();
+            let img = rotated_card(width, height, angle);
+            let mut builder = Detector::builder();
+            builder.card_edge_width(0).detection_window_width(10);
+            if degrees != 0.0 {
+                assert!(
+                    !builder.build(300, 320).detect(&mut img.clone()),
+                    "{}",
+                    degrees
+                );
+            }
+
+            let mut detector = builder.max_rotation(7_f32.to_radians()).build(300, 320);
+            assert!(detector.detect(&mut img.clone()), "{}", degrees);
+            let rotation = detector.rotation().unwrap();
+            assert!(
+                (rotation - angle).abs() < 0.5_f32.to_radians(),
+                "{} {}",
+                degrees,
+                rotation
+            );
+
+            let corners = detector.corners().unwrap();
+            let (cos, sin) = (angle.cos(), angle.sin());
+            for (corner, [x, y]) in
+                corners
+                    .iter()
+                    .zip(&[[left, top], [right, top], [right, bottom], [left, bottom]])
+            {
+                let (dx, dy) = (x - centre[0], y - centre[1]);
+                let expected = [
+                    centre[0] + dx * cos - dy * sin,
+                    centre[1] + dx * sin + dy * cos,
+                ];
+                assert!(
+                    (corner[0] as f32 - expected[0]).abs() <= 3.0
+                        && (corner[1] as f32 - expected[1]).abs() <= 3.0,
+                    "{} {:?} {:?}",
+                    degrees,
+                    corner,
+                    expected
+                );
+            }
+        }
+
+        // a photo of a card that is nearly square to the boundary
+        let mut img = image::open("test_images/uno-7.jpg").unwrap().to_rgba();
+        let mut detector = Detector::builder()
+            .card_edge_width(0)
+            .detection_window_width(20)
+            .max_rotation(5_f32.to_radians())
+            .build(img.width() as usize, img.height() as usize);
+        assert!(detector.detect(&mut img));
+        assert!(detector.rotation().unwrap().abs() < 2_f32.to_radians());
+
+        // rotated too far
+        let mut img = rotated_card(width, height, 4_f32.to_radians());
+        let mut detector = Detector::builder()
+            .detection_window_width(10)
+            .max_rotation(1_f32.to_radians())
+            .build(300, 320);
+        assert!(!detector.detect(&mut img));
+        assert_eq!(detector.rotation(), None);
+    }
+
+    #[test]
+    fn test_search_rotated() {
+        let mut builder = Detector::builder();
+        builder.search(true);
+        let img = rotated_card(300, 320, 3_f32.to_radians());
+        let mut detector = builder.build(300, 320);
+        assert!(detector.detect(&mut img.clone()));
+        let rotation = detector.rotation().unwrap();
+        assert!(
+            (rotation - 3_f32.to_radians()).abs() < 1_f32.to_radians(),
+            "{}",
+            rotation
+        );
+
+        builder.max_rotation(2_f32.to_radians());
+        assert!(!builder.build(300, 320).detect(&mut img.clone()));
+    }
+
+    #[test]
+    fn test_detect_landscape() {
+        let (width, height) = (280, 200);
+        let format = CardFormat::POKER.landscape();
+        let boundary = get_corners(280, 200, format, MARGIN);
+        let img = card(width, height, boundary);
+
+        let mut detector = Detector::builder()
+            .card_edge_width(0)
+            .detection_window_width(10)
+            .format(format)
+            .build(280, 200);
+        assert!(detector.detect(&mut img.clone()));
+        assert_eq!(
+            detector.corners(),
+            Some([[10, 10], [270, 10], [270, 190], [10, 190]])
+        );
+        let extracted = detector.extract(&img, 70).unwrap();
+        assert_eq!(extracted.dimensions(), (70, 50));
+
+        // square cards are only found by a search for square cards
+        let mut img = card(200, 160, Rectangle([[20, 20], [119, 119]]));
+        let mut builder = Detector::builder();
+        builder.search(true).format(CardFormat::SQUARE);
+        assert!(builder.build(200, 160).detect(&mut img));
+    }
+
+    #[test]
+    fn test_detect_placement() {
+        let (width, height) = (400, 240);
+        let boundary = guide(400, 240, CardFormat::POKER, MARGIN, Placement::Centred);
+        let img = card(width, height, boundary);
+        let [left, top] = *boundary.top_left();
+        let [right, bottom] = *boundary.bottom_right();
+
+        let mut builder = Detector::builder();
+        builder.card_edge_width(3).detection_window_width(10);
+        assert!(!builder.build(400, 240).detect(&mut img.clone()));
+        for &placement in &[
+            Placement::Centred,
+            Placement::Anchor { x: 0.5, y: 0.5 },
+            Placement::Rectangle(boundary),
+        ] {
+            let mut detector = builder.placement(placement).build(400, 240);
+            let mut img = img.clone();
+            assert!(detector.detect(&mut img), "{:?}", placement);
+            assert_eq!(
+                detector.corners(),
+                Some([[left, top], [right, top], [right, bottom], [left, bottom]])
+            );
+            assert_eq!(img.get_pixel(200, top as u32 - 3), &HIT_COLOUR);
+        }
+
+        // the sides are drawn inside the frame when the boundary is against its edges
+        let boundary = Rectangle([[0, 0], [171, 239]]);
+        let mut img = card(width, height, boundary);
+        let mut detector = builder
+            .placement(Placement::Rectangle(boundary))
+            .build(400, 240);
+        assert!(detector.detect(&mut img));
+        assert_eq!(img.get_pixel(80, 0), &HIT_COLOUR);
+        assert_eq!(img.get_pixel(0, 120), &HIT_COLOUR);
+        assert_eq!(img.get_pixel(174, 120), &HIT_COLOUR);
+        // the bottom side is on the last row, where no edges are found
+        assert_eq!(img.get_pixel(80, 239), &MISS_COLOUR);
+    }
+
+    #[test]
+    fn test_detect_corners() {
+        let mut img = image::open("test_images/uno-7.jpg").unwrap().to_rgba();
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let mut detector = Detector::builder()
+            .card_edge_width(0)
+            .detection_window_width(20)
+            .build(width, height);
+        assert!(detector.detect(&mut img));
+        let boundary = get_corners(width, height, CardFormat::POKER, MARGIN);
+        let [left, top] = *boundary.top_left();
+        let [right, bottom] = *boundary.bottom_right();
+        assert_eq!(
+            detector.corners(),
+            Some([[left, top], [right, top], [right, bottom], [left, bottom]])
+        );
+        assert_eq!(detector.rotation(), Some(0.0));
+    }
+
     #[bench]
     fn bench_detect(b: &mut Bencher) {
         let img = image::open("test_images/uno-7.jpg").unwrap().to_rgba();
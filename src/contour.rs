@@ -0,0 +1,459 @@
+//! Trace Canny edge maps into contours and approximate them with polygons
+//!
+//! Edges from the Canny edge detector are thin, one pixel wide, curves. Following connected edge
+//! pixels gives an ordered contour for each curve, which can then be simplified into a polygon to
+//! find shapes such as the quadrilateral outline of a card.
+use crate::data::Point;
+use crate::edge::{EdgeMap, Window};
+#[cfg(target_arch = "wasm32")]
+use crate::performance;
+
+/// Largest distance, in pixels along either axis, between the ends of a closed contour
+///
+/// Canny often loses the pixel right at a sharp corner so a small gap is allowed.
+const CLOSE_DISTANCE: usize = 2;
+
+/// Neighbouring pixel offsets, edge neighbours before corner neighbours
+///
+/// Following edge neighbours first stops a contour cutting across a staircase and leaving the
+/// skipped pixel behind as a contour of its own.
+const NEIGHBOURS: [[isize; 2]; 8] = [
+    [1, 0],
+    [0, 1],
+    [-1, 0],
+    [0, -1],
+    [1, 1],
+    [-1, 1],
+    [-1, -1],
+    [1, -1],
+];
+
+/// An ordered chain of connected edge pixels
+#[derive(Debug, PartialEq, Clone)]
+pub struct Contour {
+    points: Vec<Point>,
+    closed: bool,
+}
+
+impl Contour {
+    /// The pixels in the contour, in order
+    #[must_use]
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    /// Does the contour loop back on itself
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Length of the contour in pixels
+    #[must_use]
+    pub fn perimeter(&self) -> f32 {
+        let length: f32 = self.points.windows(2).map(|p| distance(p[0], p[1])).sum();
+        match (self.closed, self.points.first(), self.points.last()) {
+            (true, Some(&first), Some(&last)) => length + distance(first, last),
+            _ => length,
+        }
+    }
+
+    /// Approximate the contour with a polygon using the Douglas–Peucker algorithm
+    ///
+    /// No pixel in the contour is further than `epsilon` pixels from the resulting polygon.
+    #[must_use]
+    pub fn simplify(&self, epsilon: f32) -> Polygon {
+        let points = &self.points;
+        let vertices = if points.len() < 3 {
+            points.clone()
+        } else if self.closed {
+            // split the loop at the pixel furthest from the first one and simplify both halves
+            let split = (1..points.len())
+                .max_by(|&a, &b| {
+                    distance(points[0], points[a]).total_cmp(&distance(points[0], points[b]))
+                })
+                .unwrap_or(1);
+            let mut vertices = douglas_peucker(&points[..=split], epsilon);
+            let mut rest: Vec<Point> = points[split..].to_vec();
+            rest.push(points[0]);
+            vertices.pop();
+            vertices.extend(douglas_peucker(&rest, epsilon));
+            vertices.pop();
+
+            // the first pixel is always kept by the split, drop it if it is not really a corner
+            let n = vertices.len();
+            if n > 3 && line_distance(vertices[0], vertices[n - 1], vertices[1]) <= epsilon {
+                vertices.remove(0);
+            }
+            vertices
+        } else {
+            douglas_peucker(points, epsilon)
+        };
+
+        Polygon {
+            vertices,
+            closed: self.closed,
+        }
+    }
+}
+
+/// A polygon approximating a contour
+#[derive(Debug, PartialEq, Clone)]
+pub struct Polygon {
+    vertices: Vec<Point>,
+    closed: bool,
+}
+
+impl Polygon {
+    /// The vertices of the polygon, in order
+    #[must_use]
+    pub fn vertices(&self) -> &[Point] {
+        &self.vertices
+    }
+
+    /// Is the polygon closed, or is it an open polyline
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Area enclosed by a closed polygon
+    ///
+    /// Open polylines have no area.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn area(&self) -> f32 {
+        if !self.closed {
+            return 0.0;
+        }
+        let n = self.vertices.len();
+        let twice_area: f32 = (0..n)
+            .map(|i| {
+                let (a, b) = (self.vertices[i], self.vertices[(i + 1) % n]);
+                a[0] as f32 * b[1] as f32 - b[0] as f32 * a[1] as f32
+            })
+            .sum();
+        twice_area.abs() / 2.0
+    }
+
+    /// Is the polygon closed and convex
+    #[must_use]
+    pub fn is_convex(&self) -> bool {
+        let n = self.vertices.len();
+        if !self.closed || n < 3 {
+            return false;
+        }
+        let turns: Vec<f32> = (0..n)
+            .map(|i| {
+                cross(
+                    self.vertices[i],
+                    self.vertices[(i + 1) % n],
+                    self.vertices[(i + 2) % n],
+                )
+            })
+            .collect();
+        turns.iter().all(|&t| t >= 0.0) || turns.iter().all(|&t| t <= 0.0)
+    }
+}
+
+/// A four sided polygon found in an edge map
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Quadrilateral {
+    /// The corners in order around the outline
+    pub corners: [Point; 4],
+    /// Area enclosed by the quadrilateral in pixels
+    pub area: f32,
+    /// Whether the quadrilateral is convex
+    pub convex: bool,
+}
+
+/// Preallocated contour tracer
+pub struct ContourTracer {
+    width: usize,
+    height: usize,
+    visited: Vec<u64>,
+}
+
+impl ContourTracer {
+    /// Create a contour tracer for images of the given size
+    #[must_use]
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            visited: vec![0; (width * height).div_ceil(64)],
+        }
+    }
+
+    /// Follow connected edge pixels into contours
+    ///
+    /// Contours are started from edge pixels within the window. Each edge pixel belongs to
+    /// exactly one contour. Where edges branch the branches are reported as separate contours.
+    ///
+    /// # Panics
+    ///
+    /// If the edge map is not the same size as the tracer.
+    pub fn trace<T: Window>(&mut self, edges: &EdgeMap, window: &T) -> Vec<Contour> {
+        #[cfg(target_arch = "wasm32")]
+        let _timer = performance::Timer::new("contour::trace");
+        assert!(
+            edges.width() == self.width && edges.height() == self.height,
+            "edge map must be the same size as the contour tracer"
+        );
+        for word in &mut self.visited {
+            *word = 0;
+        }
+
+        let mut contours = vec![];
+        for point in window.process() {
+            if !edges.is_edge(point[0], point[1]) || self.is_visited(point) {
+                continue;
+            }
+            self.visit(point);
+
+            // follow the edge away from the start in one direction then the other
+            let mut backward = self.follow(edges, point);
+            let forward = self.follow(edges, point);
+            backward.reverse();
+            backward.push(point);
+            backward.extend(forward);
+
+            let (first, last) = (backward[0], backward[backward.len() - 1]);
+            let closed = backward.len() > 2 * CLOSE_DISTANCE
+                && first[0].max(last[0]) - first[0].min(last[0]) <= CLOSE_DISTANCE
+                && first[1].max(last[1]) - first[1].min(last[1]) <= CLOSE_DISTANCE;
+            contours.push(Contour {
+                points: backward,
+                closed,
+            });
+        }
+        contours
+    }
+
+    /// Find four sided closed contours within the window
+    ///
+    /// Each closed contour is simplified allowing pixels to be up to `tolerance` times its
+    /// perimeter away from the polygon. Those that simplify to four corners are returned, largest
+    /// first.
+    pub fn quadrilaterals<T: Window>(
+        &mut self,
+        edges: &EdgeMap,
+        window: &T,
+        tolerance: f32,
+    ) -> Vec<Quadrilateral> {
+        let mut quadrilaterals: Vec<Quadrilateral> = self
+            .trace(edges, window)
+            .into_iter()
+            .filter(Contour::is_closed)
+            .filter_map(|contour| {
+                let polygon = contour.simplify(tolerance * contour.perimeter());
+                match *polygon.vertices() {
+                    [a, b, c, d] => Some(Quadrilateral {
+                        corners: [a, b, c, d],
+                        area: polygon.area(),
+                        convex: polygon.is_convex(),
+                    }),
+                    _ => None,
+                }
+            })
+            .collect();
+        quadrilaterals.sort_by(|a, b| b.area.total_cmp(&a.area));
+        quadrilaterals
+    }
+
+    fn is_visited(&self, point: Point) -> bool {
+        let i = point[1] * self.width + point[0];
+        self.visited[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn visit(&mut self, point: Point) {
+        let i = point[1] * self.width + point[0];
+        self.visited[i / 64] |= 1 << (i % 64);
+    }
+
+    /// Walk from a point along unvisited edge pixels until the edge ends
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_wrap)]
+    fn follow(&mut self, edges: &EdgeMap, start: Point) -> Vec<Point> {
+        let mut chain = vec![];
+        let mut current = start;
+        'walk: loop {
+            for offset in &NEIGHBOURS {
+                let (x, y) = (
+                    current[0] as isize + offset[0],
+                    current[1] as isize + offset[1],
+                );
+                if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+                    continue;
+                }
+                let next = [x as usize, y as usize];
+                if edges.is_edge(next[0], next[1]) && !self.is_visited(next) {
+                    self.visit(next);
+                    chain.push(next);
+                    current = next;
+                    continue 'walk;
+                }
+            }
+            return chain;
+        }
+    }
+}
+
+/// Simplify an open polyline, keeping both ends
+fn douglas_peucker(points: &[Point], epsilon: f32) -> Vec<Point> {
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    let mut stack = vec![(0, points.len() - 1)];
+    while let Some((start, end)) = stack.pop() {
+        let furthest = (start + 1..end)
+            .map(|i| (i, line_distance(points[i], points[start], points[end])))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, d)) = furthest {
+            if d > epsilon {
+                keep[i] = true;
+                stack.push((start, i));
+                stack.push((i, end));
+            }
+        }
+    }
+
+    points
+        .iter()
+        .zip(keep)
+        .filter_map(|(&p, keep)| if keep { Some(p) } else { None })
+        .collect()
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn distance(a: Point, b: Point) -> f32 {
+    (a[0] as f32 - b[0] as f32).hypot(a[1] as f32 - b[1] as f32)
+}
+
+/// Distance from a point to the line through two others
+fn line_distance(point: Point, start: Point, end: Point) -> f32 {
+    let length = distance(start, end);
+    if length == 0.0 {
+        distance(point, start)
+    } else {
+        cross(start, end, point).abs() / length
+    }
+}
+
+/// Z component of the cross product of (b - a) and (c - b)
+#[allow(clippy::cast_precision_loss)]
+fn cross(a: Point, b: Point, c: Point) -> f32 {
+    let (ab_x, ab_y) = (b[0] as f32 - a[0] as f32, b[1] as f32 - a[1] as f32);
+    let (bc_x, bc_y) = (c[0] as f32 - b[0] as f32, c[1] as f32 - b[1] as f32);
+    ab_x * bc_y - ab_y * bc_x
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::{Contour, ContourTracer};
+    use crate::data::Rectangle;
+    use crate::edge::{CannyBuilder, RectangleWindow};
+
+    #[test]
+    fn test_simplify() {
+        // an L shape with a slightly wobbly long side
+        let contour = Contour {
+            points: vec![
+                [0, 0],
+                [1, 0],
+                [2, 1],
+                [3, 0],
+                [4, 0],
+                [4, 1],
+                [4, 2],
+                [4, 3],
+            ],
+            closed: false,
+        };
+        let polygon = contour.simplify(1.0);
+        assert_eq!(polygon.vertices(), &[[0, 0], [4, 0], [4, 3]]);
+        assert!(!polygon.is_closed());
+        assert!(polygon.area().abs() < f32::EPSILON);
+
+        let polygon = contour.simplify(0.4);
+        assert_eq!(
+            polygon.vertices(),
+            &[[0, 0], [1, 0], [2, 1], [3, 0], [4, 0], [4, 3]]
+        );
+    }
+
+    #[test]
+    fn test_polygon() {
+        let square = Contour {
+            points: vec![
+                [2, 0],
+                [4, 0],
+                [4, 2],
+                [4, 4],
+                [2, 4],
+                [0, 4],
+                [0, 2],
+                [0, 0],
+                [1, 0],
+            ],
+            closed: true,
+        };
+        let polygon = square.simplify(0.5);
+        assert_eq!(polygon.vertices(), &[[4, 0], [4, 4], [0, 4], [0, 0]]);
+        assert!((polygon.area() - 16.0).abs() < f32::EPSILON);
+        assert!(polygon.is_convex());
+
+        let arrow = Contour {
+            points: vec![[0, 0], [4, 2], [0, 4], [2, 2]],
+            closed: true,
+        };
+        let polygon = arrow.simplify(0.1);
+        assert_eq!(polygon.vertices().len(), 4);
+        assert!(!polygon.is_convex());
+        assert!((polygon.area() - 4.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn test_quadrilaterals() {
+        // white square on a black background with a one pixel grey border to avoid ties
+        let img = RgbaImage::from_fn(100, 80, |x, y| {
+            if (21..79).contains(&x) && (16..64).contains(&y) {
+                Rgba([255, 255, 255, 255])
+            } else if (20..80).contains(&x) && (15..65).contains(&y) {
+                Rgba([128, 128, 128, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        let mut canny = CannyBuilder::new(100, 80).build();
+        let edges = canny.detect_edges(&img);
+        let window = RectangleWindow::new(Rectangle([[0, 0], [100, 80]]));
+        let mut tracer = ContourTracer::new(100, 80);
+
+        let contours = tracer.trace(edges, &window);
+        assert_eq!(contours.len(), 1, "{:?}", contours);
+        assert!(contours[0].is_closed());
+        assert_eq!(contours[0].points().len(), edges.points().count());
+
+        let quadrilaterals = tracer.quadrilaterals(edges, &window, 0.02);
+        assert_eq!(quadrilaterals.len(), 1);
+        let quadrilateral = quadrilaterals[0];
+        assert!(quadrilateral.convex);
+        assert!(
+            (quadrilateral.area - 59.0 * 49.0).abs() < 150.0,
+            "{:?}",
+            quadrilateral
+        );
+        for corner in &[[20, 15], [79, 15], [79, 64], [20, 64]] {
+            assert!(
+                quadrilateral.corners.iter().any(|c| {
+                    (c[0] as isize - corner[0]).abs() <= 2 && (c[1] as isize - corner[1]).abs() <= 2
+                }),
+                "{:?}",
+                quadrilateral
+            );
+        }
+    }
+}
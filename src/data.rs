@@ -1,9 +1,21 @@
+//! Points and rectangles in images
+//!
+//! Positions are whole pixels, with x to the right and y down from the top left of the image.
+
+/// Pixel position, x then y
 pub type Point = [usize; 2];
 
+/// Axis aligned rectangle from its top left corner to its bottom right corner
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Rectangle(pub [Point; 2]);
 
 impl Rectangle {
+    /// Create a rectangle from its corners
+    ///
+    /// # Panics
+    ///
+    /// If the bottom right corner is not below and to the right of the top left corner.
+    #[must_use]
     pub fn new(top_left: Point, bottom_right: Point) -> Self {
         assert!(
             bottom_right[0] > top_left[0] && bottom_right[1] > top_left[1],
@@ -12,38 +24,64 @@ impl Rectangle {
         Self([top_left, bottom_right])
     }
 
+    /// Rectangle covering an image of the given size
+    ///
+    /// # Panics
+    ///
+    /// If the image has no pixels.
+    #[must_use]
     pub fn from_dimensions(width: usize, height: usize) -> Self {
         Self::new([0, 0], [width, height])
     }
 
+    /// Top left corner
+    #[must_use]
     pub fn top_left(&self) -> &Point {
         &self.0[0]
     }
 
+    /// Bottom right corner
+    #[must_use]
     pub fn bottom_right(&self) -> &Point {
         &self.0[1]
     }
 
+    /// Iterate over the top left and bottom right corners
+    #[allow(clippy::iter_without_into_iter)]
     pub fn iter(&self) -> std::slice::Iter<Point> {
         self.0.iter()
     }
 
+    /// Columns from the left edge up to the right edge
+    #[must_use]
     pub fn x_range(&self) -> std::ops::Range<usize> {
         (self.top_left()[0])..(self.bottom_right()[0])
     }
 
+    /// Rows from the top edge up to the bottom edge
+    #[must_use]
     pub fn y_range(&self) -> std::ops::Range<usize> {
         (self.top_left()[1])..(self.bottom_right()[1])
     }
 
+    /// Distance from the left edge to the right edge
+    #[must_use]
     pub fn width(&self) -> usize {
         self.bottom_right()[0] - self.top_left()[0]
     }
 
+    /// Distance from the top edge to the bottom edge
+    #[must_use]
     pub fn height(&self) -> usize {
         self.bottom_right()[1] - self.top_left()[1]
     }
 
+    /// Move every edge `n` pixels outwards
+    ///
+    /// # Panics
+    ///
+    /// If the top left corner would move past (0, 0).
+    #[must_use]
     pub fn grow(&self, n: usize) -> Self {
         assert!(
             n <= self.top_left()[0] && n <= self.top_left()[1],
@@ -55,6 +93,8 @@ impl Rectangle {
         )
     }
 
+    /// Move every edge `n` pixels outwards, stopping at the edges of `rect`
+    #[must_use]
     pub fn clamped_grow(&self, n: usize, rect: &Rectangle) -> Self {
         Self::new([
             clamp_sub(self.top_left()[0], n, rect.top_left()[0]),
@@ -65,6 +105,12 @@ impl Rectangle {
         ])
     }
 
+    /// Move every edge `n` pixels inwards
+    ///
+    /// # Panics
+    ///
+    /// If the rectangle would be empty.
+    #[must_use]
     pub fn shrink(&self, n: usize) -> Self {
         assert!(
             n <= self.bottom_right()[0] && n <= self.bottom_right()[1],
@@ -76,6 +122,8 @@ impl Rectangle {
         )
     }
 
+    /// Move every edge `n` pixels inwards, stopping at the opposite edges of `rect`
+    #[must_use]
     pub fn clamped_shrink(&self, n: usize, rect: &Rectangle) -> Self {
         Self::new([
             clamp_add(self.top_left()[0], n, rect.bottom_right()[0]),
@@ -86,6 +134,8 @@ impl Rectangle {
         ])
     }
 
+    /// Is `other` strictly inside this rectangle
+    #[must_use]
     pub fn contains(&self, other: &Rectangle) -> bool {
         self[0][0] < other[0][0]
             && self[0][1] < other[0][1]
@@ -93,6 +143,7 @@ impl Rectangle {
             && self[1][1] > other[1][1]
     }
 
+    /// Draw the outline of the rectangle onto an image
     pub fn draw<P: image::Pixel + 'static>(&self, img: &mut image::ImageBuffer<P, Vec<P::Subpixel>>, colour: P) {
         for y in self.iter().map(|&p| p[1]) {
             for x in self.x_range() {
@@ -1,29 +1,85 @@
 //! Canny edge detection
 //!
 //! Most of this is taken from https://github.com/nicksrandall/edge-detection-wasm
-use image::{buffer::ConvertBuffer, GrayImage, Rgba, RgbaImage};
+use image::{GrayImage, Pixel, Rgba, RgbaImage};
 use std::{
     cmp::{max, min},
     f32, i16,
-    ops::Deref,
+    ops::Range,
+    sync::Arc,
 };
 
 use crate::data::{Point, Rectangle};
 #[cfg(target_arch = "wasm32")]
 use crate::performance;
 
+#[cfg(feature = "parallel")]
+mod parallel;
+mod morphology;
+mod pyramid;
+mod streaming;
+
+pub use morphology::{Morphology, StructuringElement};
+pub use pyramid::{Pyramid, ScaledEdges};
+pub use streaming::{EdgeRow, StreamingCanny, StreamingCannyBuilder};
+
+/// A run of horizontally adjacent Points on one row of a window
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    /// Row of the Points
+    pub y: usize,
+    /// Columns of the Points
+    pub x: Range<usize>,
+}
+
 /// A window within which the edge detection should run
 ///
 /// This is useful when you don't need to detect edges in the whole image.
-pub trait Window: Copy {
+///
+/// Points must be iterated in row major order, top to bottom then left to right, without
+/// repeats. Windows can be combined with `union`, `intersection` and `difference`.
+pub trait Window: Clone {
     /// Iterate over a series of Points
     type Iterator: Iterator<Item = Point>;
 
+    /// Iterate over a series of Spans
+    type Spans: Iterator<Item = Span>;
+
     /// The Points that need to be visited by the edge detection operator
     fn gradient(&self) -> Self::Iterator;
 
     /// The Points that need to be visited by non-maximum suppression and hysteresis
+    ///
+    /// These are the Points visited by the edge detection operator whose neighbours are all
+    /// visited by the edge detection operator too.
     fn process(&self) -> Self::Iterator;
+
+    /// The Points visited by the edge detection operator as row Spans
+    ///
+    /// Spans are in the same order as `gradient`, non-empty and never touch another Span on the
+    /// same row.
+    fn gradient_spans(&self) -> Self::Spans;
+
+    /// The Points visited by non-maximum suppression and hysteresis as row Spans
+    fn process_spans(&self) -> Self::Spans;
+
+    /// Is the Point visited by the edge detection operator
+    fn contains(&self, point: Point) -> bool;
+
+    /// Combine with another window to visit the Points in either
+    fn union<W: Window>(self, other: W) -> CombinedWindow<Self, W> {
+        CombinedWindow::new(self, other, Combination::Union)
+    }
+
+    /// Combine with another window to visit the Points in both
+    fn intersection<W: Window>(self, other: W) -> CombinedWindow<Self, W> {
+        CombinedWindow::new(self, other, Combination::Intersection)
+    }
+
+    /// Combine with another window to visit the Points in this window but not the other
+    fn difference<W: Window>(self, other: W) -> CombinedWindow<Self, W> {
+        CombinedWindow::new(self, other, Combination::Difference)
+    }
 }
 
 /// Simplest form of edge detection window
@@ -34,13 +90,22 @@ pub struct RectangleWindow {
     rectangle: Rectangle,
 }
 
+impl RectangleWindow {
+    /// Create a new rectangular detection window
+    #[must_use]
+    pub fn new(rectangle: Rectangle) -> Self {
+        Self { rectangle }
+    }
+}
+
 impl Window for RectangleWindow {
     type Iterator = RectangleWindowIterator;
+    type Spans = RectangleSpans;
 
     fn gradient(&self) -> RectangleWindowIterator {
         let next = *self.rectangle.top_left();
         RectangleWindowIterator {
-            rectangle: self.rectangle.clone(),
+            rectangle: self.rectangle,
             next: Some(next),
         }
     }
@@ -53,6 +118,18 @@ impl Window for RectangleWindow {
             next: Some(next),
         }
     }
+
+    fn gradient_spans(&self) -> RectangleSpans {
+        RectangleSpans::new(self.rectangle, None)
+    }
+
+    fn process_spans(&self) -> RectangleSpans {
+        RectangleSpans::new(self.rectangle.shrink(1), None)
+    }
+
+    fn contains(&self, point: Point) -> bool {
+        self.rectangle.x_range().contains(&point[0]) && self.rectangle.y_range().contains(&point[1])
+    }
 }
 
 /// Edge detection window shaped like a window frame
@@ -111,56 +188,591 @@ impl RectangleInRectangleWindow {
 
 impl Window for RectangleInRectangleWindow {
     type Iterator = RectangleInRectangleWindowIterator;
+    type Spans = RectangleSpans;
 
     fn gradient(&self) -> RectangleInRectangleWindowIterator {
-        let next = *self.outer.top_left();
-        RectangleInRectangleWindowIterator {
-            window: self.clone(),
-            next: Some(next),
-        }
+        RectangleInRectangleWindowIterator::new(self.outer, self.inner)
     }
 
     fn process(&self) -> RectangleInRectangleWindowIterator {
-        let window = RectangleInRectangleWindow {
-            outer: self.outer.shrink(1),
-            inner: self.inner.grow(1),
+        RectangleInRectangleWindowIterator::new(self.outer.shrink(1), self.inner.grow(1))
+    }
+
+    fn gradient_spans(&self) -> RectangleSpans {
+        RectangleSpans::new(self.outer, Some(self.inner))
+    }
+
+    fn process_spans(&self) -> RectangleSpans {
+        RectangleSpans::new(self.outer.shrink(1), Some(self.inner.grow(1)))
+    }
+
+    fn contains(&self, point: Point) -> bool {
+        let inside = |rectangle: Rectangle| {
+            rectangle.x_range().contains(&point[0]) && rectangle.y_range().contains(&point[1])
         };
-        let next = *window.outer.top_left();
-        RectangleInRectangleWindowIterator {
-            window,
-            next: Some(next),
-        }
+        inside(self.outer) && !inside(self.inner)
     }
 }
 
 #[allow(missing_docs)]
 pub struct RectangleInRectangleWindowIterator {
-    window: RectangleInRectangleWindow,
-    next: Option<Point>,
+    spans: RectangleSpans,
+    span: Span,
+}
+
+impl RectangleInRectangleWindowIterator {
+    fn new(outer: Rectangle, inner: Rectangle) -> Self {
+        Self {
+            spans: RectangleSpans::new(outer, Some(inner)),
+            span: Span { y: 0, x: 0..0 },
+        }
+    }
 }
 
 impl Iterator for RectangleInRectangleWindowIterator {
     type Item = Point;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.next {
-            Some(next) => {
-                if next[0] == self.window.inner[0][0] - 1 && next[1] >= self.window.inner[0][1] && next[1] < self.window.inner[1][1] {
-                    self.next = Some([self.window.inner[1][0], next[1]]);
-                } else if next[0] == self.window.outer[1][0] - 1 {
-                    if next[1] == self.window.outer[1][1] - 1{
-                        self.next = None;
-                    } else {
-                        self.next = Some([self.window.outer[0][0], next[1] + 1]);
+        loop {
+            if let Some(x) = self.span.x.next() {
+                return Some([x, self.span.y]);
+            }
+            self.span = self.spans.next()?;
+        }
+    }
+}
+
+/// Spans of the Points within an outer Rectangle but not within an optional inner Rectangle
+pub struct RectangleSpans {
+    outer: Rectangle,
+    inner: Option<Rectangle>,
+    y: usize,
+    right: Option<Span>,
+}
+
+impl RectangleSpans {
+    fn new(outer: Rectangle, inner: Option<Rectangle>) -> Self {
+        Self {
+            outer,
+            inner,
+            y: outer[0][1],
+            right: None,
+        }
+    }
+}
+
+impl Iterator for RectangleSpans {
+    type Item = Span;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(right) = self.right.take() {
+            return Some(right);
+        }
+        let x = self.outer.x_range();
+        while !x.is_empty() && self.y < self.outer[1][1] {
+            let y = self.y;
+            self.y += 1;
+            match self.inner {
+                Some(inner) if inner.y_range().contains(&y) => {
+                    let left = Span {
+                        y,
+                        x: x.start..min(x.end, inner[0][0]),
+                    };
+                    let right = Span {
+                        y,
+                        x: max(x.start, inner[1][0])..x.end,
+                    };
+                    match (left.x.is_empty(), right.x.is_empty()) {
+                        (true, true) => {}
+                        (true, false) => return Some(right),
+                        (false, true) => return Some(left),
+                        (false, false) => {
+                            self.right = Some(right);
+                            return Some(left);
+                        }
                     }
-                } else {
-                    self.next = Some([next[0] + 1, next[1]]);
                 }
-                Some(next)
+                _ => return Some(Span { y, x }),
+            }
+        }
+        None
+    }
+}
+
+/// Spans of a row major series of Points
+pub struct PointSpans<I: Iterator<Item = Point>> {
+    points: std::iter::Peekable<I>,
+}
+
+impl<I: Iterator<Item = Point>> PointSpans<I> {
+    fn new(points: I) -> Self {
+        Self {
+            points: points.peekable(),
+        }
+    }
+}
+
+impl<I: Iterator<Item = Point>> Iterator for PointSpans<I> {
+    type Item = Span;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let [x, y] = self.points.next()?;
+        let mut end = x + 1;
+        while self.points.next_if_eq(&[end, y]).is_some() {
+            end += 1;
+        }
+        Some(Span { y, x: x..end })
+    }
+}
+
+/// A bitmap of the Points in a window
+struct Mask {
+    width: usize,
+    height: usize,
+    bits: Vec<u64>,
+}
+
+impl Mask {
+    fn from_fn<F: Fn(usize, usize) -> bool>(width: usize, height: usize, f: F) -> Self {
+        let mut bits = vec![0; (width * height).div_ceil(64)];
+        for y in 0..height {
+            for x in 0..width {
+                if f(x, y) {
+                    let i = y * width + x;
+                    bits[i / 64] |= 1 << (i % 64);
+                }
+            }
+        }
+        Self {
+            width,
+            height,
+            bits,
+        }
+    }
+
+    fn contains(&self, x: usize, y: usize) -> bool {
+        let i = y * self.width + x;
+        self.bits[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    /// The Points whose neighbours are all in this mask, and so can be processed
+    fn shrink(&self) -> Self {
+        Self::from_fn(self.width, self.height, |x, y| {
+            x > 0
+                && y > 0
+                && x < self.width - 1
+                && y < self.height - 1
+                && (y - 1..=y + 1).all(|y| (x - 1..=x + 1).all(|x| self.contains(x, y)))
+        })
+    }
+}
+
+/// Edge detection window of any shape, defined by a bitmap
+///
+/// Detect edges using the Points set in the bitmap.
+#[derive(Clone)]
+pub struct MaskWindow {
+    gradient: Arc<Mask>,
+    process: Arc<Mask>,
+}
+
+impl MaskWindow {
+    /// Create a new window containing the Points for which `f` returns true
+    pub fn from_fn<F: Fn(usize, usize) -> bool>(width: usize, height: usize, f: F) -> Self {
+        let gradient = Mask::from_fn(width, height, f);
+        let process = gradient.shrink();
+        Self {
+            gradient: Arc::new(gradient),
+            process: Arc::new(process),
+        }
+    }
+
+    /// Create a new window containing the non-zero pixels of an image
+    #[must_use]
+    pub fn from_image(mask: &GrayImage) -> Self {
+        #[allow(clippy::cast_possible_truncation)]
+        Self::from_fn(mask.width() as usize, mask.height() as usize, |x, y| {
+            mask.get_pixel(x as u32, y as u32)[0] != 0
+        })
+    }
+}
+
+impl Window for MaskWindow {
+    type Iterator = MaskWindowIterator;
+    type Spans = PointSpans<MaskWindowIterator>;
+
+    fn gradient(&self) -> MaskWindowIterator {
+        MaskWindowIterator::new(self.gradient.clone())
+    }
+
+    fn process(&self) -> MaskWindowIterator {
+        MaskWindowIterator::new(self.process.clone())
+    }
+
+    fn gradient_spans(&self) -> Self::Spans {
+        PointSpans::new(self.gradient())
+    }
+
+    fn process_spans(&self) -> Self::Spans {
+        PointSpans::new(self.process())
+    }
+
+    fn contains(&self, point: Point) -> bool {
+        point[0] < self.gradient.width
+            && point[1] < self.gradient.height
+            && self.gradient.contains(point[0], point[1])
+    }
+}
+
+#[allow(missing_docs)]
+pub struct MaskWindowIterator {
+    mask: Arc<Mask>,
+    word: usize,
+    bits: u64,
+}
+
+impl MaskWindowIterator {
+    fn new(mask: Arc<Mask>) -> Self {
+        let bits = mask.bits.first().copied().unwrap_or(0);
+        Self {
+            mask,
+            word: 0,
+            bits,
+        }
+    }
+}
+
+impl Iterator for MaskWindowIterator {
+    type Item = Point;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.bits == 0 {
+            self.word += 1;
+            self.bits = *self.mask.bits.get(self.word)?;
+        }
+        let i = self.word * 64 + self.bits.trailing_zeros() as usize;
+        self.bits &= self.bits - 1;
+        Some([i % self.mask.width, i / self.mask.width])
+    }
+}
+
+/// Edge detection window shaped like a polygon
+///
+/// Detect edges using the Points within the outline polygon but not within the optional hole
+/// polygon. The polygons may be convex or concave, a Point is inside if the centre of its pixel
+/// is inside by the even-odd rule.
+#[derive(Clone)]
+pub struct PolygonWindow {
+    mask: MaskWindow,
+}
+
+impl PolygonWindow {
+    /// Create a new window inside the outline polygon
+    #[must_use]
+    pub fn new(width: usize, height: usize, outline: &[[f32; 2]]) -> Self {
+        Self::with_hole(width, height, outline, &[])
+    }
+
+    /// Create a new window inside the outline polygon but outside the hole polygon
+    #[must_use]
+    pub fn with_hole(
+        width: usize,
+        height: usize,
+        outline: &[[f32; 2]],
+        hole: &[[f32; 2]],
+    ) -> Self {
+        #[allow(clippy::cast_precision_loss)]
+        let mask = MaskWindow::from_fn(width, height, |x, y| {
+            let centre = [x as f32 + 0.5, y as f32 + 0.5];
+            polygon_contains(outline, centre) && !polygon_contains(hole, centre)
+        });
+        Self { mask }
+    }
+}
+
+impl Window for PolygonWindow {
+    type Iterator = MaskWindowIterator;
+    type Spans = PointSpans<MaskWindowIterator>;
+
+    fn gradient(&self) -> MaskWindowIterator {
+        self.mask.gradient()
+    }
+
+    fn process(&self) -> MaskWindowIterator {
+        self.mask.process()
+    }
+
+    fn gradient_spans(&self) -> Self::Spans {
+        self.mask.gradient_spans()
+    }
+
+    fn process_spans(&self) -> Self::Spans {
+        self.mask.process_spans()
+    }
+
+    fn contains(&self, point: Point) -> bool {
+        self.mask.contains(point)
+    }
+}
+
+/// Is the point inside the polygon by the even-odd rule
+fn polygon_contains(polygon: &[[f32; 2]], point: [f32; 2]) -> bool {
+    let mut inside = false;
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        if (a[1] > point[1]) != (b[1] > point[1]) {
+            let crossing = a[0] + (point[1] - a[1]) / (b[1] - a[1]) * (b[0] - a[0]);
+            if point[0] < crossing {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+/// How the Points of two windows are combined
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Combination {
+    /// Points in either window
+    Union,
+    /// Points in both windows
+    Intersection,
+    /// Points in the first window but not the second
+    Difference,
+}
+
+/// Edge detection window made by combining two other windows
+///
+/// The Points of the two windows are merged so no Point is visited twice. The Points processed
+/// are those of the combined shape whose neighbours are all in the combined shape, so a seam
+/// between two windows that touch is processed even though it is on the edge of both.
+#[derive(Clone)]
+pub struct CombinedWindow<A: Window, B: Window> {
+    a: A,
+    b: B,
+    combination: Combination,
+}
+
+impl<A: Window, B: Window> CombinedWindow<A, B> {
+    /// Create a new window combining two others
+    pub fn new(a: A, b: B, combination: Combination) -> Self {
+        Self { a, b, combination }
+    }
+
+    fn iter(&self, process: bool) -> CombinedWindowIterator<A, B> {
+        CombinedWindowIterator {
+            a: self.a.gradient().peekable(),
+            b: self.b.gradient().peekable(),
+            window: self.clone(),
+            process,
+        }
+    }
+}
+
+impl<A: Window, B: Window> Window for CombinedWindow<A, B> {
+    type Iterator = CombinedWindowIterator<A, B>;
+    type Spans = PointSpans<CombinedWindowIterator<A, B>>;
+
+    fn gradient(&self) -> CombinedWindowIterator<A, B> {
+        self.iter(false)
+    }
+
+    fn process(&self) -> CombinedWindowIterator<A, B> {
+        self.iter(true)
+    }
+
+    fn gradient_spans(&self) -> Self::Spans {
+        PointSpans::new(self.gradient())
+    }
+
+    fn process_spans(&self) -> Self::Spans {
+        PointSpans::new(self.process())
+    }
+
+    fn contains(&self, point: Point) -> bool {
+        match self.combination {
+            Combination::Union => self.a.contains(point) || self.b.contains(point),
+            Combination::Intersection => self.a.contains(point) && self.b.contains(point),
+            Combination::Difference => self.a.contains(point) && !self.b.contains(point),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+pub struct CombinedWindowIterator<A: Window, B: Window> {
+    a: std::iter::Peekable<A::Iterator>,
+    b: std::iter::Peekable<B::Iterator>,
+    window: CombinedWindow<A, B>,
+    process: bool,
+}
+
+impl<A: Window, B: Window> CombinedWindowIterator<A, B> {
+    /// The next Point of the combined gradient Points
+    fn next_gradient(&mut self) -> Option<Point> {
+        let row_major = |p: &Point| (p[1], p[0]);
+        loop {
+            let order = match (self.a.peek(), self.b.peek()) {
+                (Some(a), Some(b)) => row_major(a).cmp(&row_major(b)),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => return None,
+            };
+            let (in_a, in_b) = match order {
+                std::cmp::Ordering::Less => (self.a.next(), None),
+                std::cmp::Ordering::Greater => (None, self.b.next()),
+                std::cmp::Ordering::Equal => (self.a.next(), self.b.next()),
+            };
+            let keep = match self.window.combination {
+                Combination::Union => true,
+                Combination::Intersection => in_a.is_some() && in_b.is_some(),
+                Combination::Difference => in_a.is_some() && in_b.is_none(),
+            };
+            if keep {
+                return in_a.or(in_b);
+            }
+            if self.window.combination != Combination::Union && self.a.peek().is_none() {
+                return None;
+            }
+        }
+    }
+}
+
+impl<A: Window, B: Window> Iterator for CombinedWindowIterator<A, B> {
+    type Item = Point;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let point = self.next_gradient()?;
+            if !self.process {
+                return Some(point);
+            }
+            let [x, y] = point;
+            if x > 0
+                && y > 0
+                && (y - 1..=y + 1).all(|y| (x - 1..=x + 1).all(|x| self.window.contains([x, y])))
+            {
+                return Some(point);
+            }
+        }
+    }
+}
+
+/// Where the Points of a window are stored in Canny's buffers
+///
+/// Only the Points visited by the edge detection operator have storage, so memory scales with
+/// the window rather than the image. Points are stored span by span in row major order, which
+/// keeps each span contiguous.
+#[derive(Clone, Debug, Default)]
+struct Layout {
+    /// First row with any Points
+    top: usize,
+    /// Index into `spans` of the first span of each row from `top`, followed by the span count
+    rows: Vec<usize>,
+    /// Gradient spans along with the index of their first Point
+    spans: Vec<(Span, usize)>,
+    /// Process spans
+    process: Vec<Span>,
+    /// Number of Points stored
+    len: usize,
+}
+
+impl Layout {
+    fn new<T: Window>(window: &T) -> Self {
+        Self::from_spans(window.gradient_spans(), window.process_spans().collect())
+    }
+
+    fn from_spans<I: IntoIterator<Item = Span>>(spans: I, process: Vec<Span>) -> Self {
+        let mut layout = Self::default();
+        for span in spans {
+            if layout.spans.is_empty() {
+                layout.top = span.y;
+            }
+            while layout.top + layout.rows.len() <= span.y {
+                layout.rows.push(layout.spans.len());
+            }
+            let len = span.x.len();
+            layout.spans.push((span, layout.len));
+            layout.len += len;
+        }
+        layout.rows.push(layout.spans.len());
+        layout.process = process;
+        layout
+    }
+
+    /// Layout of the pixels within `radius` of any Point, clipped to the image
+    ///
+    /// Overlapping spans are merged, so the pixels under a kernel centred on any Point of a span
+    /// are stored contiguously row by row.
+    fn padded(&self, radius: usize, width: usize, height: usize) -> Self {
+        let rows = self.rows();
+        let rows = rows.start.saturating_sub(radius)..min(rows.end + radius, height);
+        let mut spans = vec![];
+        for y in rows {
+            let mut xs: Vec<Range<usize>> = (y.saturating_sub(radius)..=y + radius)
+                .flat_map(|y| self.row(y))
+                .map(|(span, _)| {
+                    span.x.start.saturating_sub(radius)..min(span.x.end + radius, width)
+                })
+                .collect();
+            xs.sort_by_key(|x| x.start);
+            for x in xs {
+                match spans.last_mut() {
+                    Some(Span { y: last_y, x: last }) if *last_y == y && x.start <= last.end => {
+                        last.end = max(last.end, x.end);
+                    }
+                    _ => spans.push(Span { y, x }),
+                }
+            }
+        }
+        Self::from_spans(spans, vec![])
+    }
+
+    /// Rows with any Points
+    fn rows(&self) -> Range<usize> {
+        self.top..self.top + self.rows.len() - 1
+    }
+
+    /// Number of Points in the longest span
+    fn widest(&self) -> usize {
+        self.spans.iter().map(|(span, _)| span.x.len()).max().unwrap_or(0)
+    }
+
+    /// The gradient spans on row `y` with the index of their first Point
+    fn row(&self, y: usize) -> &[(Span, usize)] {
+        match y.checked_sub(self.top) {
+            Some(row) if row + 1 < self.rows.len() => {
+                &self.spans[self.rows[row]..self.rows[row + 1]]
             }
-            None => None
+            _ => &[],
         }
     }
+
+    /// Index of the Point (x, y), if it is visited by the edge detection operator
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        self.row(y)
+            .iter()
+            .find(|(span, _)| span.x.contains(&x))
+            .map(|(span, start)| start + x - span.x.start)
+    }
+
+    /// Point stored at an index
+    fn point(&self, i: usize) -> Point {
+        let span = self.spans.partition_point(|(_, start)| *start <= i) - 1;
+        let (Span { y, x }, start) = &self.spans[span];
+        [x.start + i - start, *y]
+    }
+
+    /// Index of the first Point of a span of processed Points and of the Points above and below
+    ///
+    /// The Points start one to the left of the span, so the Points at `x` are at
+    /// `x - span.x.start + 1` from each index.
+    fn process_rows(&self, span: &Span) -> [usize; 3] {
+        let index = |y| {
+            self.index(span.x.start - 1, y)
+                .expect("processed Points are surrounded by gradient Points")
+        };
+        [index(span.y - 1), index(span.y), index(span.y + 1)]
+    }
 }
 
 /// Build a Canny edge detector
@@ -171,6 +783,13 @@ pub struct CannyBuilder<T: Window> {
     low_threshold: Option<f32>,
     high_threshold: Option<f32>,
     line_colour: Option<Rgba<u8>>,
+    blur_sigma: Option<f32>,
+    gradient_kernel: Option<GradientKernel>,
+    border_mode: Option<BorderMode>,
+    colour_gradient: Option<ColourGradient>,
+    auto_threshold: Option<AutoThreshold>,
+    sub_pixel: bool,
+    arithmetic: Option<Arithmetic>,
 }
 
 impl CannyBuilder<RectangleWindow> {
@@ -199,6 +818,13 @@ impl<T: Window> CannyBuilder<T> {
             low_threshold: None,
             high_threshold: None,
             line_colour: None,
+            blur_sigma: None,
+            gradient_kernel: None,
+            border_mode: None,
+            colour_gradient: None,
+            auto_threshold: None,
+            sub_pixel: false,
+            arithmetic: None,
         }
     }
 
@@ -222,57 +848,177 @@ impl<T: Window> CannyBuilder<T> {
         self
     }
 
-    /// Build a canny edge detector
-    pub fn build(&self) -> Canny<T> {
-        Canny::new(
-            self.width,
-            self.height,
-            self.low_threshold.unwrap_or(150.0),
-            self.high_threshold.unwrap_or(300.0),
-            self.line_colour.unwrap_or(Rgba([0, 0, 0, 255])),
-            self.window,
-        )
+    /// Set the standard deviation of the Gaussian blur applied before the gradient
+    ///
+    /// Smoothing the image first removes noise that would otherwise show up as spurious edges. A
+    /// sigma of zero, the default, skips the blur stage entirely.
+    pub fn blur_sigma(&mut self, blur_sigma: f32) -> &mut CannyBuilder<T> {
+        self.blur_sigma = Some(blur_sigma);
+        self
     }
-}
 
-/// Canny edge detector
-pub struct Canny<T: Window> {
-    gx: Vec<i16>,
-    gy: Vec<i16>,
-    filtered: Vec<f32>,
-    supressed: Vec<f32>,
+    /// Set the kernel used to calculate the image gradient
+    ///
+    /// Defaults to the 3x3 Sobel operator. Each kernel has a different gain so the hysteresis
+    /// thresholds usually need adjusting when the kernel is changed.
+    pub fn gradient_kernel(&mut self, gradient_kernel: GradientKernel) -> &mut CannyBuilder<T> {
+        self.gradient_kernel = Some(gradient_kernel);
+        self
+    }
 
-    /// width of the image
-    pub width: usize,
-    ///height of the image
-    pub height: usize,
-    low_threshold: f32,
-    high_threshold: f32,
-    line_colour: Rgba<u8>,
+    /// Set how the blur and image gradient are calculated at the edge of the image
+    ///
+    /// Defaults to replicating the outermost pixels.
+    pub fn border_mode(&mut self, border_mode: BorderMode) -> &mut CannyBuilder<T> {
+        self.border_mode = Some(border_mode);
+        self
+    }
+
+    /// Set how the colour channels of the image are combined into a single gradient
+    ///
+    /// Defaults to the gradient of the luma. The colour modes find edges between regions of
+    /// similar brightness, such as a red card on a brown table, at roughly three times the cost.
+    pub fn colour_gradient(&mut self, colour_gradient: ColourGradient) -> &mut CannyBuilder<T> {
+        self.colour_gradient = Some(colour_gradient);
+        self
+    }
+
+    /// Choose the hysteresis thresholds automatically for every image
+    ///
+    /// When set the low and high thresholds are ignored and instead derived from the gradient
+    /// magnitudes within the window. The values used for the last image can be read back with
+    /// `Canny::thresholds`.
+    pub fn auto_threshold(&mut self, auto_threshold: AutoThreshold) -> &mut CannyBuilder<T> {
+        self.auto_threshold = Some(auto_threshold);
+        self
+    }
+
+    /// Also locate each edge to a fraction of a pixel
+    ///
+    /// The refined positions are available from `EdgeMap::sub_pixel_points`. Disabled by
+    /// default.
+    pub fn sub_pixel(&mut self, sub_pixel: bool) -> &mut CannyBuilder<T> {
+        self.sub_pixel = sub_pixel;
+        self
+    }
+
+    /// Set the number types used for the gradient magnitudes
+    ///
+    /// Defaults to floating point. See `Arithmetic` for the differences.
+    pub fn arithmetic(&mut self, arithmetic: Arithmetic) -> &mut CannyBuilder<T> {
+        self.arithmetic = Some(arithmetic);
+        self
+    }
+
+    /// Build a canny edge detector
+    ///
+    /// # Panics
+    ///
+    /// If the window has Points outside the image, such as a mask larger than the image.
+    pub fn build(&self) -> Canny<T> {
+        Canny::new(self)
+    }
+}
+
+/// Canny edge detector
+pub struct Canny<T: Window> {
+    /// pixels under the blur and gradient kernels of the window
+    source: Layout,
+    /// one channel of the image at the pixels of `source`
+    plane: Vec<u8>,
+    blurred: Vec<u8>,
+    blur_scratch: BlurScratch,
+    blur_kernel: Vec<u32>,
+    /// running totals for the gradient, one set for each band calculated in parallel
+    gradient_scratch: Vec<GradientScratch>,
+    suppressed: Magnitudes,
+    edges: EdgeMap,
+    /// gradients of each colour channel in turn, unused for the luma gradient
+    channel_gx: Vec<i16>,
+    channel_gy: Vec<i16>,
+    channel_magnitude: Magnitudes,
+
+    /// width of the image
+    pub width: usize,
+    ///height of the image
+    pub height: usize,
+    low_threshold: f32,
+    high_threshold: f32,
+    line_colour: Rgba<u8>,
+    gradient_kernel: GradientKernel,
+    border_mode: BorderMode,
+    colour_gradient: ColourGradient,
+    auto_threshold: Option<AutoThreshold>,
+    histogram: Vec<u32>,
+    sub_pixel: bool,
     window: T,
+    #[cfg(feature = "parallel")]
+    threads: usize,
 }
 
 impl<T: Window> Canny<T> {
-    pub(crate) fn new(
-        width: usize,
-        height: usize,
-        low_threshold: f32,
-        high_threshold: f32,
-        line_colour: Rgba<u8>,
-        window: T,
-    ) -> Self {
+    fn new(builder: &CannyBuilder<T>) -> Self {
+        let (width, height) = (builder.width, builder.height);
+        let layout = Layout::new(&builder.window);
+        assert!(
+            layout
+                .spans
+                .iter()
+                .all(|(span, _)| span.y < height && span.x.end <= width),
+            "the detection window must fit within the image"
+        );
+        let blur_kernel = gaussian_kernel(builder.blur_sigma.unwrap_or(0.0));
+        let gradient_kernel = builder.gradient_kernel.unwrap_or_default();
+        let radius = max(blur_kernel.len(), gradient_kernel.size()) / 2;
+        let source = layout.padded(radius, width, height);
+        let colour_gradient = builder.colour_gradient.unwrap_or_default();
+        let arithmetic = builder.arithmetic.unwrap_or_default();
+        let channels = if colour_gradient == ColourGradient::Luma {
+            0
+        } else {
+            3 * layout.len
+        };
+        #[cfg(feature = "parallel")]
+        let threads = std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get);
+        #[cfg(not(feature = "parallel"))]
+        let threads = 1;
         Self {
-            gx: vec![0; width * height],
-            gy: vec![0; width * height],
-            filtered: vec![0_f32; width * height],
-            supressed: vec![0_f32; width * height],
+            plane: vec![0; source.len],
+            source,
+            blurred: if blur_kernel.is_empty() {
+                vec![]
+            } else {
+                vec![0; layout.len]
+            },
+            blur_scratch: BlurScratch::new(&blur_kernel, &layout),
+            blur_kernel,
+            gradient_scratch: (0..threads)
+                .map(|_| GradientScratch::new(layout.widest()))
+                .collect(),
+            suppressed: Magnitudes::new(arithmetic, layout.len),
+            channel_gx: vec![0; channels],
+            channel_gy: vec![0; channels],
+            channel_magnitude: Magnitudes::new(arithmetic, channels),
+            edges: EdgeMap::new(width, height, layout, arithmetic),
 
             width,
             height,
-            low_threshold,
-            high_threshold,
-            line_colour,
-            window,
+            low_threshold: builder.low_threshold.unwrap_or(150.0),
+            high_threshold: builder.high_threshold.unwrap_or(300.0),
+            line_colour: builder.line_colour.unwrap_or(Rgba([0, 0, 0, 255])),
+            gradient_kernel,
+            border_mode: builder.border_mode.unwrap_or_default(),
+            colour_gradient,
+            auto_threshold: builder.auto_threshold,
+            histogram: if builder.auto_threshold.is_some() {
+                vec![0; HISTOGRAM_BINS]
+            } else {
+                vec![]
+            },
+            sub_pixel: builder.sub_pixel,
+            window: builder.window.clone(),
+            #[cfg(feature = "parallel")]
+            threads,
         }
     }
 
@@ -281,50 +1027,677 @@ impl<T: Window> Canny<T> {
         self.line_colour
     }
 
-    /// Detect edges in an image
+    /// Hysteresis low and high thresholds
+    ///
+    /// When automatic thresholds are enabled these are the values chosen for the last image.
+    #[must_use]
+    pub fn thresholds(&self) -> (f32, f32) {
+        (self.low_threshold, self.high_threshold)
+    }
+
+    /// Gradient calculated for the last image
+    ///
+    /// Along with the gradient this includes the magnitudes left after non-maximum suppression,
+    /// so other detectors can be built on the same gradient without recalculating it.
+    #[must_use]
+    pub fn gradient_field(&self) -> GradientField<'_> {
+        GradientField {
+            width: self.width,
+            height: self.height,
+            layout: &self.edges.layout,
+            gx: &self.edges.gx,
+            gy: &self.edges.gy,
+            magnitude: &self.edges.magnitude,
+            suppressed: &self.suppressed,
+        }
+    }
+
+    /// Detect edges in an image and draw them onto it using the line colour
     pub fn detect(&mut self, src: &mut RgbaImage) {
+        self.detect_edges(src);
+
         #[cfg(target_arch = "wasm32")]
-        let timer = performance::Timer::new("canny::setup-struct");
-        // TODO: convert into existing image when possible
-        let mut result = src;
-        let src: GrayImage = result.convert();
-        let src = src.deref();
-        #[cfg(target_arch = "wasm32")]
-        std::mem::drop(timer);
+        let _timer = performance::Timer::new("canny::render");
+        self.edges.render(src, self.line_colour);
+    }
 
-        gradient(
-            self.width,
-            self.height,
-            src,
-            &mut self.gx,
-            &mut self.gy,
-            &mut self.filtered,
-            &self.window,
-        );
+    /// Blur the image and calculate its gradient, combining the colour channels if needed
+    ///
+    /// Each channel is read into the same window sized plane in turn.
+    fn gradient<M: Magnitude>(
+        &mut self,
+        src: &RgbaImage,
+        g: &mut [M],
+        channel_magnitude: &mut [M],
+    ) {
+        let layout = &self.edges.layout;
+        let source = &self.source;
+        let (width, height) = (self.width, self.height);
+        let (kernel, border) = (self.gradient_kernel, self.border_mode);
+        let (plane, blurred, blur_kernel) = (&mut self.plane, &mut self.blurred, &self.blur_kernel);
+        let blur_scratch = &mut self.blur_scratch;
+        let gradient_scratch = &mut self.gradient_scratch;
+        #[cfg(feature = "parallel")]
+        let threads = self.threads;
+        let mut channel_gradient =
+            |channel: &dyn Fn(&Rgba<u8>) -> u8, gx: &mut [i16], gy: &mut [i16], g: &mut [M]| {
+                #[cfg(target_arch = "wasm32")]
+                let timer = performance::Timer::new("canny::setup-struct");
+                read_plane(src, source, plane, channel);
+                #[cfg(target_arch = "wasm32")]
+                std::mem::drop(timer);
+
+                if !blur_kernel.is_empty() {
+                    blur(
+                        width,
+                        height,
+                        source,
+                        plane,
+                        layout,
+                        blurred,
+                        blur_scratch,
+                        blur_kernel,
+                        border,
+                    );
+                }
 
-        non_maximum_suppression(
-            self.width,
-            &self.filtered,
-            &self.gx,
-            &self.gy,
-            &mut self.supressed,
-            &self.window,
-        );
+                #[cfg(feature = "parallel")]
+                parallel::gradient(
+                    threads,
+                    width,
+                    height,
+                    source,
+                    plane,
+                    layout,
+                    gx,
+                    gy,
+                    g,
+                    gradient_scratch,
+                    kernel,
+                    border,
+                );
+                #[cfg(not(feature = "parallel"))]
+                gradient(
+                    width,
+                    height,
+                    source,
+                    plane,
+                    &layout.spans,
+                    gx,
+                    gy,
+                    g,
+                    &mut gradient_scratch[0],
+                    kernel,
+                    border,
+                );
+            };
+
+        if self.colour_gradient == ColourGradient::Luma {
+            channel_gradient(
+                &|pixel| pixel.to_luma()[0],
+                &mut self.edges.gx,
+                &mut self.edges.gy,
+                g,
+            );
+        } else {
+            for channel in 0..3 {
+                let range = channel * layout.len..(channel + 1) * layout.len;
+                channel_gradient(
+                    &|pixel| pixel[channel],
+                    &mut self.channel_gx[range.clone()],
+                    &mut self.channel_gy[range.clone()],
+                    &mut channel_magnitude[range],
+                );
+            }
+            combine_channels(
+                self.colour_gradient,
+                &self.channel_gx,
+                &self.channel_gy,
+                channel_magnitude,
+                &mut self.edges.gx,
+                &mut self.edges.gy,
+                g,
+            );
+        }
+    }
+
+    /// Run every stage with magnitudes of type `M`
+    fn stages<M: Magnitude>(
+        &mut self,
+        src: &RgbaImage,
+        g: &mut [M],
+        suppressed: &mut [M],
+        channel_magnitude: &mut [M],
+    ) {
+        self.gradient(src, g, channel_magnitude);
+
+        if let Some(auto_threshold) = self.auto_threshold {
+            // a flat frame has nothing to choose from, keep the previous thresholds
+            if let Some((low, high)) = select_thresholds(
+                &self.edges.layout,
+                g,
+                &mut self.histogram,
+                auto_threshold,
+                &self.window,
+            ) {
+                self.low_threshold = low;
+                self.high_threshold = high;
+            }
+        }
+
+        let layout = &self.edges.layout;
+        #[cfg(feature = "parallel")]
+        {
+            parallel::non_maximum_suppression(
+                self.threads,
+                layout,
+                g,
+                &self.edges.gx,
+                &self.edges.gy,
+                suppressed,
+            );
+            parallel::hysteresis(
+                self.threads,
+                layout,
+                suppressed,
+                &mut self.edges.edges,
+                self.low_threshold,
+                self.high_threshold,
+            );
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            non_maximum_suppression(
+                layout,
+                &layout.process,
+                g,
+                &self.edges.gx,
+                &self.edges.gy,
+                suppressed,
+                0,
+            );
+            hysteresis(
+                layout,
+                &layout.process,
+                &layout.rows(),
+                suppressed,
+                &mut self.edges.edges,
+                0,
+                self.low_threshold,
+                self.high_threshold,
+            );
+        }
+
+        self.edges.sub_pixel.clear();
+        if self.sub_pixel {
+            sub_pixel(
+                &self.edges.layout,
+                g,
+                &self.edges.gx,
+                &self.edges.gy,
+                &self.edges.edges,
+                &mut self.edges.sub_pixel,
+            );
+        }
+    }
 
-        hysteresis(
-            self.width as u32,
-            self.height as u32,
-            &self.supressed,
-            &mut result,
-            self.low_threshold,
-            self.high_threshold,
-            self.line_colour,
-            &self.window,
+    /// Detect edges in an image
+    ///
+    /// The image is left untouched, the edges found are returned as an `EdgeMap`. The map is
+    /// reused between calls so it is only valid until the next detection.
+    pub fn detect_edges(&mut self, src: &RgbaImage) -> &EdgeMap {
+        // the magnitudes are taken out while the stages run so their type can be matched
+        let mut magnitudes = (
+            std::mem::take(&mut self.edges.magnitude),
+            std::mem::take(&mut self.suppressed),
+            std::mem::take(&mut self.channel_magnitude),
         );
+        match &mut magnitudes {
+            (Magnitudes::Float(g), Magnitudes::Float(suppressed), Magnitudes::Float(channels)) => {
+                self.stages(src, g, suppressed, channels);
+            }
+            (
+                Magnitudes::Integer(g),
+                Magnitudes::Integer(suppressed),
+                Magnitudes::Integer(channels),
+            ) => self.stages(src, g, suppressed, channels),
+            _ => unreachable!("the magnitudes all use the same arithmetic"),
+        }
+        (self.edges.magnitude, self.suppressed, self.channel_magnitude) = magnitudes;
+
+        &self.edges
+    }
+}
+
+/// Edges found by the Canny edge detector
+///
+/// Along with whether each pixel is an edge the map holds the gradient magnitude and direction
+/// of every pixel in the detection window. Pixels outside the window are never edges and have no
+/// gradient.
+pub struct EdgeMap {
+    width: usize,
+    height: usize,
+    layout: Layout,
+    edges: Vec<u64>,
+    gx: Vec<i16>,
+    gy: Vec<i16>,
+    magnitude: Magnitudes,
+    /// refined position of each edge, when enabled
+    sub_pixel: Vec<[f32; 2]>,
+}
+
+impl EdgeMap {
+    fn new(width: usize, height: usize, layout: Layout, arithmetic: Arithmetic) -> Self {
+        let len = layout.len;
+        Self {
+            width,
+            height,
+            layout,
+            edges: vec![0; len.div_ceil(64)],
+            gx: vec![0; len],
+            gy: vec![0; len],
+            magnitude: Magnitudes::new(arithmetic, len),
+            sub_pixel: vec![],
+        }
+    }
+
+    /// Width of the image
+    #[must_use]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height of the image
+    #[must_use]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Is the pixel at (x, y) an edge
+    #[must_use]
+    pub fn is_edge(&self, x: usize, y: usize) -> bool {
+        self.layout
+            .index(x, y)
+            .is_some_and(|i| self.edges[i / 64] & (1 << (i % 64)) != 0)
+    }
+
+    /// Gradient magnitude at (x, y)
+    #[must_use]
+    pub fn magnitude(&self, x: usize, y: usize) -> f32 {
+        self.layout
+            .index(x, y)
+            .map_or(0.0, |i| self.magnitude.get(i).sqrt())
+    }
+
+    /// Gradient direction at (x, y) in radians
+    ///
+    /// The angle is measured clockwise from the positive x axis, as y increases down the image,
+    /// and is in the range -π to π.
+    #[must_use]
+    pub fn direction(&self, x: usize, y: usize) -> f32 {
+        self.layout.index(x, y).map_or(0.0, |i| {
+            f32::from(self.gy[i]).atan2(f32::from(self.gx[i]))
+        })
+    }
+
+    /// Iterate over the edge Points in row major order
+    #[must_use]
+    pub fn points(&self) -> EdgeMapIterator<'_> {
+        EdgeMapIterator::new(&self.layout, &self.edges)
+    }
+
+    /// Sub-pixel positions of the edge Points, in the same order as `points`
+    ///
+    /// Each edge is moved along its gradient direction to the peak of a parabola through the
+    /// gradient magnitudes of the edge and its two neighbours, so it is never more than half a
+    /// step to a neighbour from the edge Point. That is half a pixel horizontally or vertically
+    /// and about 0.71 pixels diagonally. Pixel centres are at whole numbers. Empty unless enabled
+    /// with `CannyBuilder::sub_pixel`.
+    #[must_use]
+    pub fn sub_pixel_points(&self) -> &[[f32; 2]] {
+        &self.sub_pixel
+    }
+
+    /// Draw the edges onto an image
+    ///
+    /// # Panics
+    ///
+    /// If the image is smaller than the edge map.
+    pub fn render(&self, img: &mut RgbaImage, colour: Rgba<u8>) {
+        assert!(img.width() as usize >= self.width && img.height() as usize >= self.height);
+        for [x, y] in self.points() {
+            #[allow(clippy::cast_possible_truncation)]
+            img.put_pixel(x as u32, y as u32, colour);
+        }
+    }
+}
+
+/// Iterator over the edge Points in an `EdgeMap` or `Morphology`
+pub struct EdgeMapIterator<'a> {
+    layout: &'a Layout,
+    edges: &'a [u64],
+    word: usize,
+    bits: u64,
+}
+
+impl<'a> EdgeMapIterator<'a> {
+    fn new(layout: &'a Layout, edges: &'a [u64]) -> Self {
+        Self {
+            layout,
+            edges,
+            word: 0,
+            bits: edges.first().copied().unwrap_or(0),
+        }
+    }
+}
+
+impl Iterator for EdgeMapIterator<'_> {
+    type Item = Point;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.bits == 0 {
+            self.word += 1;
+            self.bits = *self.edges.get(self.word)?;
+        }
+        let i = self.word * 64 + self.bits.trailing_zeros() as usize;
+        self.bits &= self.bits - 1;
+        Some(self.layout.point(i))
+    }
+}
+
+/// Read only view of the gradient calculated by a `Canny` edge detector
+///
+/// Values are looked up by their (x, y) position in the image. The gradient is only calculated
+/// for Points in the window's `gradient` iterator, and non-maximum suppression only for those in
+/// its `process` iterator. Everywhere else the gradient is zero.
+///
+/// The gradient is the raw output of the gradient kernel, so its scale depends on the kernel and
+/// colour gradient used.
+pub struct GradientField<'a> {
+    width: usize,
+    height: usize,
+    layout: &'a Layout,
+    gx: &'a [i16],
+    gy: &'a [i16],
+    magnitude: &'a Magnitudes,
+    suppressed: &'a Magnitudes,
+}
+
+impl<'a> GradientField<'a> {
+    /// Width of the image
+    #[must_use]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height of the image
+    #[must_use]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Is the gradient calculated at (x, y)
+    #[must_use]
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        self.layout.index(x, y).is_some()
+    }
+
+    /// Horizontal and vertical gradient at (x, y)
+    ///
+    /// Positive values mean the image gets brighter to the right and down.
+    #[must_use]
+    pub fn gradient(&self, x: usize, y: usize) -> [i16; 2] {
+        self.layout
+            .index(x, y)
+            .map_or([0, 0], |i| [self.gx[i], self.gy[i]])
+    }
+
+    /// Gradient magnitude at (x, y)
+    #[must_use]
+    pub fn magnitude(&self, x: usize, y: usize) -> f32 {
+        self.layout
+            .index(x, y)
+            .map_or(0.0, |i| self.magnitude.get(i).sqrt())
+    }
+
+    /// Gradient orientation at (x, y) in radians
+    ///
+    /// The angle is measured clockwise from the positive x axis, as y increases down the image,
+    /// and is in the range -π to π.
+    #[must_use]
+    pub fn orientation(&self, x: usize, y: usize) -> f32 {
+        self.layout.index(x, y).map_or(0.0, |i| {
+            f32::from(self.gy[i]).atan2(f32::from(self.gx[i]))
+        })
+    }
+
+    /// Gradient magnitude at (x, y) after non-maximum suppression
+    ///
+    /// Zero unless the Point is a local maximum across the edge.
+    #[must_use]
+    pub fn suppressed(&self, x: usize, y: usize) -> f32 {
+        self.layout
+            .index(x, y)
+            .map_or(0.0, |i| self.suppressed.get(i).sqrt())
+    }
+
+    /// Iterate over the gradient a span at a time, in row major order
+    ///
+    /// Much faster than looking up each Point when working through the whole field.
+    pub fn spans(&self) -> impl Iterator<Item = GradientSpan<'a>> + 'a {
+        let (gx, gy, magnitude, suppressed) = (self.gx, self.gy, self.magnitude, self.suppressed);
+        self.layout.spans.iter().map(move |(span, start)| {
+            let i = *start..start + span.x.len();
+            GradientSpan {
+                span: span.clone(),
+                gx: &gx[i.clone()],
+                gy: &gy[i],
+                start: *start,
+                magnitude,
+                suppressed,
+            }
+        })
+    }
+}
+
+/// Gradient of a span of Points in a `GradientField`
+///
+/// The gradient for the Point at `x` is at `x - span.x.start` in each slice.
+#[derive(Clone, Debug)]
+pub struct GradientSpan<'a> {
+    /// The Points covered
+    pub span: Span,
+    /// Horizontal gradients
+    pub gx: &'a [i16],
+    /// Vertical gradients
+    pub gy: &'a [i16],
+    start: usize,
+    magnitude: &'a Magnitudes,
+    suppressed: &'a Magnitudes,
+}
+
+impl GradientSpan<'_> {
+    /// Gradient magnitude of the Point at `x`
+    ///
+    /// # Panics
+    ///
+    /// If `x` is not in the span.
+    #[must_use]
+    pub fn magnitude(&self, x: usize) -> f32 {
+        self.magnitude.get(self.index(x)).sqrt()
+    }
+
+    /// Gradient magnitude of the Point at `x` after non-maximum suppression
+    ///
+    /// # Panics
+    ///
+    /// If `x` is not in the span.
+    #[must_use]
+    pub fn suppressed(&self, x: usize) -> f32 {
+        self.suppressed.get(self.index(x)).sqrt()
+    }
+
+    fn index(&self, x: usize) -> usize {
+        assert!(self.span.x.contains(&x), "{} is not in the span", x);
+        self.start + x - self.span.x.start
+    }
+}
+
+/// Number types used for the gradient magnitudes, and so by every stage after the gradient
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Arithmetic {
+    /// Floating point magnitudes, with the gradient direction from an approximate arctangent
+    #[default]
+    Float,
+    /// Integer magnitudes, with the gradient direction from integer comparisons
+    ///
+    /// Faster where floating point is slow. Gradients within a fraction of a degree of the
+    /// boundary between two directions can be compared with different neighbours than `Float`
+    /// would, so the edges differ slightly.
+    ///
+    /// Not every stage is fixed point. Automatic threshold selection, the `DiZenzo` colour
+    /// gradient and sub-pixel localisation still work in floating point. The gradient, direction
+    /// sectors, non-maximum suppression and hysteresis use only integers, so with fixed
+    /// thresholds and the `Luma` or `MaxChannel` gradient the same edges are found on every
+    /// platform.
+    Integer,
+}
+
+/// Squared gradient magnitudes of every Point, of whichever type the arithmetic uses
+#[derive(Clone, Debug, PartialEq)]
+enum Magnitudes {
+    Float(Vec<f32>),
+    Integer(Vec<u32>),
+}
+
+impl Default for Magnitudes {
+    fn default() -> Self {
+        Magnitudes::Float(vec![])
     }
 }
 
-const BLACK_32: f32 = 0.0;
+impl Magnitudes {
+    fn new(arithmetic: Arithmetic, len: usize) -> Self {
+        match arithmetic {
+            Arithmetic::Float => Magnitudes::Float(vec![0.0; len]),
+            Arithmetic::Integer => Magnitudes::Integer(vec![0; len]),
+        }
+    }
+
+    /// Squared magnitude of the Point stored at `i`
+    fn get(&self, i: usize) -> f32 {
+        match self {
+            Magnitudes::Float(magnitudes) => magnitudes[i],
+            Magnitudes::Integer(magnitudes) => magnitudes[i].to_f32(),
+        }
+    }
+}
+
+/// Squared gradient magnitude, along with the arithmetic that goes with it
+pub(crate) trait Magnitude: Copy + Default + PartialOrd + Send + Sync {
+    /// Squared magnitude of a gradient
+    fn squared(h: i16, v: i16) -> Self;
+
+    /// Squared threshold, a magnitude is at least the threshold when it is at least this
+    fn threshold(threshold: f32) -> Self;
+
+    /// Squared magnitude from a floating point value
+    fn from_f32(magnitude: f32) -> Self;
+
+    /// Squared magnitude as floating point
+    fn to_f32(self) -> f32;
+
+    /// Which of the four 45° sectors the gradient direction falls in, see `sector`
+    fn sector(gx: i16, gy: i16) -> usize;
+
+    /// Store the gradients of a row of Points from their running totals
+    fn store_gradients(
+        hacc: &[i32],
+        vacc: &[i32],
+        hout: &mut [i16],
+        vout: &mut [i16],
+        out: &mut [Self],
+    ) {
+        store_gradients(hacc, vacc, hout, vout, out);
+    }
+}
+
+impl Magnitude for f32 {
+    #[inline]
+    fn squared(h: i16, v: i16) -> Self {
+        f32::from(h) * f32::from(h) + f32::from(v) * f32::from(v)
+    }
+
+    fn threshold(threshold: f32) -> Self {
+        threshold * threshold
+    }
+
+    fn from_f32(magnitude: f32) -> Self {
+        magnitude
+    }
+
+    #[inline]
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    #[inline]
+    fn sector(gx: i16, gy: i16) -> usize {
+        sector(gx, gy)
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+    fn store_gradients(
+        hacc: &[i32],
+        vacc: &[i32],
+        hout: &mut [i16],
+        vout: &mut [i16],
+        out: &mut [Self],
+    ) {
+        crate::simd::store_gradients(hacc, vacc, hout, vout, out);
+    }
+}
+
+impl Magnitude for u32 {
+    #[inline]
+    fn squared(h: i16, v: i16) -> Self {
+        let (h, v) = (u32::from(h.unsigned_abs()), u32::from(v.unsigned_abs()));
+        h * h + v * v
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn threshold(threshold: f32) -> Self {
+        let threshold = f64::from(threshold);
+        (threshold * threshold).ceil().min(f64::from(u32::MAX)) as u32
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn from_f32(magnitude: f32) -> Self {
+        magnitude.round() as u32
+    }
+
+    #[inline]
+    #[allow(clippy::cast_precision_loss)]
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    #[inline]
+    fn sector(gx: i16, gy: i16) -> usize {
+        integer_sector(gx, gy)
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+    fn store_gradients(
+        hacc: &[i32],
+        vacc: &[i32],
+        hout: &mut [i16],
+        vout: &mut [i16],
+        out: &mut [Self],
+    ) {
+        crate::simd::store_integer_gradients(hacc, vacc, hout, vout, out);
+    }
+}
 
 /// Sobel filter for detecting vertical gradients.
 const VERTICAL_SOBEL: [i32; 9] = [-1, -2, -1, 0, 0, 0, 1, 2, 1];
@@ -332,166 +1705,837 @@ const VERTICAL_SOBEL: [i32; 9] = [-1, -2, -1, 0, 0, 0, 1, 2, 1];
 /// Sobel filter for detecting horizontal gradients.
 const HORIZONTAL_SOBEL: [i32; 9] = [-1, 0, 1, -2, 0, 2, -1, 0, 1];
 
-/// Finds local maxima to make the edges thinner.
-fn non_maximum_suppression<T: Window>(
+/// Scharr filter for detecting vertical gradients.
+const VERTICAL_SCHARR: [i32; 9] = [-3, -10, -3, 0, 0, 0, 3, 10, 3];
+
+/// Scharr filter for detecting horizontal gradients.
+const HORIZONTAL_SCHARR: [i32; 9] = [-3, 0, 3, -10, 0, 10, -3, 0, 3];
+
+/// Prewitt filter for detecting vertical gradients.
+const VERTICAL_PREWITT: [i32; 9] = [-1, -1, -1, 0, 0, 0, 1, 1, 1];
+
+/// Prewitt filter for detecting horizontal gradients.
+const HORIZONTAL_PREWITT: [i32; 9] = [-1, 0, 1, -1, 0, 1, -1, 0, 1];
+
+/// 5x5 Sobel filter for detecting vertical gradients.
+#[rustfmt::skip]
+const VERTICAL_SOBEL_5: [i32; 25] = [
+    -1, -4,  -6, -4, -1,
+    -2, -8, -12, -8, -2,
+     0,  0,   0,  0,  0,
+     2,  8,  12,  8,  2,
+     1,  4,   6,  4,  1,
+];
+
+/// 5x5 Sobel filter for detecting horizontal gradients.
+#[rustfmt::skip]
+const HORIZONTAL_SOBEL_5: [i32; 25] = [
+    -1,  -2, 0,  2, 1,
+    -4,  -8, 0,  8, 4,
+    -6, -12, 0, 12, 6,
+    -4,  -8, 0,  8, 4,
+    -1,  -2, 0,  2, 1,
+];
+
+/// Convolution kernel used to calculate the image gradient
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum GradientKernel {
+    /// 3x3 Sobel operator
+    #[default]
+    Sobel,
+    /// 3x3 Scharr operator
+    ///
+    /// Better rotational symmetry than Sobel so diagonal edges, such as those of a tilted card,
+    /// are detected more evenly. Roughly four times the gain of Sobel.
+    Scharr,
+    /// 3x3 Prewitt operator
+    ///
+    /// Less smoothing than Sobel. Roughly three quarters of the gain of Sobel.
+    Prewitt,
+    /// 5x5 Sobel operator
+    ///
+    /// Smooths over a larger area which suppresses fine texture. Roughly twelve times the gain of
+    /// the 3x3 Sobel.
+    Sobel5,
+}
+
+impl GradientKernel {
+    /// Width and height of the kernel
+    #[must_use]
+    pub fn size(self) -> usize {
+        match self {
+            GradientKernel::Sobel | GradientKernel::Scharr | GradientKernel::Prewitt => 3,
+            GradientKernel::Sobel5 => 5,
+        }
+    }
+
+    /// Weights for detecting horizontal gradients in row major order
+    #[must_use]
+    pub fn horizontal(self) -> &'static [i32] {
+        match self {
+            GradientKernel::Sobel => &HORIZONTAL_SOBEL,
+            GradientKernel::Scharr => &HORIZONTAL_SCHARR,
+            GradientKernel::Prewitt => &HORIZONTAL_PREWITT,
+            GradientKernel::Sobel5 => &HORIZONTAL_SOBEL_5,
+        }
+    }
+
+    /// Weights for detecting vertical gradients in row major order
+    #[must_use]
+    pub fn vertical(self) -> &'static [i32] {
+        match self {
+            GradientKernel::Sobel => &VERTICAL_SOBEL,
+            GradientKernel::Scharr => &VERTICAL_SCHARR,
+            GradientKernel::Prewitt => &VERTICAL_PREWITT,
+            GradientKernel::Sobel5 => &VERTICAL_SOBEL_5,
+        }
+    }
+}
+
+/// How the blur and gradient stages treat kernel taps that fall outside the image
+///
+/// Only Points within half a kernel of the edge of the image are affected, which happens when
+/// the detection window touches the edge of the frame.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum BorderMode {
+    /// Use the nearest pixel in the image, `aa|abcd`
+    #[default]
+    Replicate,
+    /// Mirror the image about its outermost pixels, `cb|abcd`
+    Reflect,
+    /// Treat pixels outside the image as black, `00|abcd`
+    Zero,
+    /// Give Points whose kernel does not fit in the image no gradient, so they are never edges,
+    /// and leave them unblurred
+    Skip,
+}
+
+impl BorderMode {
+    /// Index of the pixel used for tap `i - offset` along something `len` long, if any
+    ///
+    /// The offset is passed separately so that taps before the start don't underflow.
+    fn index(self, i: usize, offset: usize, len: usize) -> Option<usize> {
+        match self {
+            BorderMode::Replicate => Some(clamp_index(i, offset, len)),
+            BorderMode::Reflect => {
+                let reflected = if i < offset {
+                    offset - i
+                } else {
+                    let i = i - offset;
+                    if i < len {
+                        i
+                    } else {
+                        (2 * (len - 1)).saturating_sub(i)
+                    }
+                };
+                Some(min(reflected, len - 1))
+            }
+            BorderMode::Zero | BorderMode::Skip => i.checked_sub(offset).filter(|&i| i < len),
+        }
+    }
+}
+
+/// How the colour channels of an image are combined into a single gradient
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ColourGradient {
+    /// Gradient of the luma, edges between colours of the same brightness are lost
+    #[default]
+    Luma,
+    /// Gradient of whichever red, green or blue channel changes the most at each Point
+    MaxChannel,
+    /// Di Zenzo's multi-channel gradient
+    ///
+    /// The direction of greatest change of the red, green and blue channels together, found from
+    /// their structure tensor. Opposing changes in different channels reinforce rather than
+    /// cancel. The direction is only known up to a half turn, which non-maximum suppression does
+    /// not mind. The magnitude is scaled so a grey image gives much the same gradient as `Luma`.
+    DiZenzo,
+}
+
+/// Combine the gradients of three colour channels, stored one after the other, into one
+#[allow(clippy::similar_names)]
+fn combine_channels<M: Magnitude>(
+    mode: ColourGradient,
+    channel_gx: &[i16],
+    channel_gy: &[i16],
+    channel_magnitude: &[M],
+    gx: &mut [i16],
+    gy: &mut [i16],
+    magnitude: &mut [M],
+) {
+    #[cfg(target_arch = "wasm32")]
+    let _timer = performance::Timer::new("canny::combine-channels");
+    let len = magnitude.len();
+    match mode {
+        ColourGradient::Luma => unreachable!("the luma gradient has a single channel"),
+        ColourGradient::MaxChannel => {
+            for (i, out) in magnitude.iter_mut().enumerate() {
+                let mut strongest = i;
+                for c in 1..3 {
+                    if channel_magnitude[c * len + i] > channel_magnitude[strongest] {
+                        strongest = c * len + i;
+                    }
+                }
+                gx[i] = channel_gx[strongest];
+                gy[i] = channel_gy[strongest];
+                *out = channel_magnitude[strongest];
+            }
+        }
+        ColourGradient::DiZenzo => {
+            for (i, out) in magnitude.iter_mut().enumerate() {
+                let (mut gxx, mut gyy, mut gxy) = (0.0, 0.0, 0.0);
+                for c in 0..3 {
+                    let (h, v) = (
+                        f32::from(channel_gx[c * len + i]),
+                        f32::from(channel_gy[c * len + i]),
+                    );
+                    gxx += h * h;
+                    gyy += v * v;
+                    gxy += h * v;
+                }
+                // largest eigenvalue of the structure tensor, averaged over the channels
+                let lambda = (gxx + gyy + (gxx - gyy).hypot(2.0 * gxy)) / 6.0;
+                let theta = 0.5 * (2.0 * gxy).atan2(gxx - gyy);
+                let length = lambda.sqrt();
+                #[allow(clippy::cast_possible_truncation)]
+                let to_i16 =
+                    |g: f32| g.round().clamp(f32::from(i16::MIN), f32::from(i16::MAX)) as i16;
+                gx[i] = to_i16(length * theta.cos());
+                gy[i] = to_i16(length * theta.sin());
+                *out = M::from_f32(lambda);
+            }
+        }
+    }
+}
+
+/// Fixed point scale of each one dimensional Gaussian weight
+const GAUSSIAN_SCALE_BITS: u32 = 8;
+
+/// Build a one dimensional fixed point Gaussian kernel for the given sigma
+///
+/// The weights sum to exactly `1 << GAUSSIAN_SCALE_BITS`. An empty kernel is returned when sigma
+/// is too small for blurring to have any effect.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn gaussian_kernel(sigma: f32) -> Vec<u32> {
+    if sigma <= 0.0 {
+        return vec![];
+    }
+    let radius = (sigma * 3.0).ceil() as i32;
+    let weights: Vec<f32> = (-radius..=radius)
+        .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    let scale = (1 << GAUSSIAN_SCALE_BITS) as f32;
+    let mut kernel: Vec<u32> = weights
+        .iter()
+        .map(|w| (w / total * scale).round() as u32)
+        .collect();
+
+    // fold any rounding error into the centre weight so flat regions stay flat
+    let centre = radius as usize;
+    let others: u32 = kernel.iter().sum::<u32>() - kernel[centre];
+    kernel[centre] = (1 << GAUSSIAN_SCALE_BITS) - others;
+    if kernel[centre] == 1 << GAUSSIAN_SCALE_BITS {
+        vec![]
+    } else {
+        kernel
+    }
+}
+
+/// Read one channel of the pixels stored by the source layout from an image
+fn read_plane<F: Fn(&Rgba<u8>) -> u8>(
+    src: &RgbaImage,
+    source: &Layout,
+    plane: &mut [u8],
+    channel: F,
+) {
+    let width = src.width() as usize;
+    for (Span { y, x }, start) in &source.spans {
+        let row = &src.as_raw()[(y * width + x.start) * 4..(y * width + x.end) * 4];
+        for (p, pixel) in plane[*start..start + x.len()].iter_mut().zip(row.chunks_exact(4)) {
+            *p = channel(Rgba::from_slice(pixel));
+        }
+    }
+}
+
+/// Scratch space for `blur`, allocated once for the widest span it will blur
+#[derive(Default)]
+struct BlurScratch {
+    /// Index of the leftmost pixel under the kernel in each row, if the row has one
+    rows: Vec<Option<usize>>,
+    /// Vertically blurred pixels of a span and of the pixels beside it under the kernel
+    columns: Vec<u32>,
+}
+
+impl BlurScratch {
+    fn new(kernel: &[u32], layout: &Layout) -> Self {
+        if kernel.is_empty() {
+            return Self::default();
+        }
+        Self {
+            rows: vec![None; kernel.len()],
+            columns: vec![0; layout.widest() + kernel.len() - 1],
+        }
+    }
+}
+
+/// Smooth the image with a Gaussian kernel.
+///
+/// Only the Points visited by the edge detection operator are blurred, Points outside the window
+/// keep their original value. The kernel is separable so each span is first blurred vertically
+/// into a row buffer wide enough for the horizontal kernel, which is then applied to that buffer.
+/// Taps outside the image follow the border mode, with `Skip` leaving Points whose kernel does
+/// not fit unblurred. The image holds only the pixels stored by `source`.
+#[allow(clippy::cast_possible_truncation, clippy::too_many_arguments)]
+fn blur(
     width: usize,
-    g: &Vec<f32>,
-    gx: &Vec<i16>,
-    gy: &Vec<i16>,
-    out: &mut Vec<f32>,
+    height: usize,
+    source: &Layout,
+    image: &mut [u8],
+    layout: &Layout,
+    out: &mut [u8],
+    scratch: &mut BlurScratch,
+    kernel: &[u32],
+    border: BorderMode,
+) {
+    #[cfg(target_arch = "wasm32")]
+    let _timer = performance::Timer::new("canny::blur");
+    let radius = kernel.len() / 2;
+    let BlurScratch { rows, columns } = scratch;
+
+    for (Span { y, x: span }, start) in &layout.spans {
+        let left = span.start.saturating_sub(radius);
+        for (k_y, row) in rows.iter_mut().enumerate() {
+            *row = border
+                .index(y + k_y, radius, height)
+                .map(|row| source_index(source, left, row));
+        }
+        let rows_fit = rows.iter().all(Option::is_some);
+        let centre = source_index(source, span.start, *y);
+        let columns = &mut columns[..span.len() + 2 * radius];
+        for (column, x) in columns.iter_mut().zip(span.start..) {
+            *column = border.index(x, radius, width).map_or(0, |x| {
+                rows.iter()
+                    .zip(kernel)
+                    .filter_map(|(&row, &vk)| row.map(|row| u32::from(image[row + x - left]) * vk))
+                    .sum::<u32>()
+            });
+        }
+        for (i, window) in columns.windows(kernel.len()).enumerate() {
+            let x = span.start + i;
+            let fits = rows_fit && x >= radius && x + radius < width;
+            out[start + i] = if border == BorderMode::Skip && !fits {
+                image[centre + i]
+            } else {
+                let acc: u32 = window.iter().zip(kernel).map(|(&c, &hk)| c * hk).sum();
+                ((acc + (1 << (2 * GAUSSIAN_SCALE_BITS - 1))) >> (2 * GAUSSIAN_SCALE_BITS)) as u8
+            };
+        }
+    }
+
+    // only write back once every Point has been blurred from the original image
+    for (Span { y, x }, start) in &layout.spans {
+        let i = source_index(source, x.start, *y);
+        image[i..i + x.len()].copy_from_slice(&out[*start..start + x.len()]);
+    }
+}
+
+/// Strategy for choosing hysteresis thresholds from the gradient magnitudes of an image
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AutoThreshold {
+    /// Scale the median non-zero gradient magnitude
+    ///
+    /// The thresholds are `low * median` and `high * median`. Values of around 1.0 and 2.0 work
+    /// well for webcam frames.
+    Median {
+        /// Multiple of the median used for the low threshold
+        low: f32,
+        /// Multiple of the median used for the high threshold
+        high: f32,
+    },
+    /// Split the gradient magnitudes into edges and background with Otsu's method
+    ///
+    /// The Otsu threshold is used as the high threshold and the low threshold is `ratio` times
+    /// that.
+    Otsu {
+        /// Ratio of the low threshold to the high threshold
+        ratio: f32,
+    },
+}
+
+/// Number of bins used for the gradient magnitude histogram
+const HISTOGRAM_BINS: usize = 256;
+
+/// Derive hysteresis thresholds from the gradient magnitudes in the window.
+///
+/// Only the Points visited by non-maximum suppression and hysteresis are considered. Returns
+/// `None` when every magnitude is zero.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn select_thresholds<T: Window, M: Magnitude>(
+    layout: &Layout,
+    g: &[M],
+    histogram: &mut [u32],
+    auto_threshold: AutoThreshold,
     window: &T,
+) -> Option<(f32, f32)> {
+    #[cfg(target_arch = "wasm32")]
+    let _timer = performance::Timer::new("canny::select-thresholds");
+    let span_magnitudes = |span: Span| {
+        let start = layout.index(span.x.start, span.y).expect("processed Points are stored");
+        &g[start..start + span.x.len()]
+    };
+    let max_magnitude = window
+        .process_spans()
+        .flat_map(span_magnitudes)
+        .fold(0.0, |a, &b| f32::max(a, b.to_f32()))
+        .sqrt();
+    if max_magnitude <= 0.0 {
+        return None;
+    }
+    let bin_width = max_magnitude / (HISTOGRAM_BINS - 1) as f32;
+
+    for bin in histogram.iter_mut() {
+        *bin = 0;
+    }
+    for magnitude in window.process_spans().flat_map(span_magnitudes) {
+        let bin = (magnitude.to_f32().sqrt() / bin_width) as usize;
+        histogram[bin.min(HISTOGRAM_BINS - 1)] += 1;
+    }
+
+    Some(match auto_threshold {
+        AutoThreshold::Median { low, high } => {
+            // flat areas are not interesting, only consider pixels with some gradient
+            let total: u32 = histogram[1..].iter().sum();
+            let mut seen = 0;
+            let median_bin = histogram[1..]
+                .iter()
+                .position(|&count| {
+                    seen += count;
+                    seen * 2 >= total
+                })
+                .map_or(0, |bin| bin + 1);
+            let median = (median_bin as f32 + 0.5) * bin_width;
+            (low * median, high * median)
+        }
+        AutoThreshold::Otsu { ratio } => {
+            let total: u32 = histogram.iter().sum();
+            let weighted_total: f32 = histogram
+                .iter()
+                .enumerate()
+                .map(|(bin, &count)| bin as f32 * count as f32)
+                .sum();
+
+            let (mut background, mut weighted_background) = (0_u32, 0_f32);
+            let (mut best_bin, mut best_variance) = (0, 0_f32);
+            for (bin, &count) in histogram.iter().enumerate() {
+                background += count;
+                if background == 0 {
+                    continue;
+                }
+                let foreground = total - background;
+                if foreground == 0 {
+                    break;
+                }
+                weighted_background += bin as f32 * count as f32;
+                let mean_background = weighted_background / background as f32;
+                let mean_foreground = (weighted_total - weighted_background) / foreground as f32;
+                let variance = background as f32
+                    * foreground as f32
+                    * (mean_background - mean_foreground).powi(2);
+                if variance > best_variance {
+                    best_bin = bin;
+                    best_variance = variance;
+                }
+            }
+            let high = (best_bin + 1) as f32 * bin_width;
+            (ratio * high, high)
+        }
+    })
+}
+
+/// Finds local maxima to make the edges thinner.
+///
+/// The output starts with the Point stored at `offset`.
+fn non_maximum_suppression<M: Magnitude>(
+    layout: &Layout,
+    process: &[Span],
+    g: &[M],
+    gx: &[i16],
+    gy: &[i16],
+    out: &mut [M],
+    offset: usize,
 ) {
     #[cfg(target_arch = "wasm32")]
     let _timer = performance::Timer::new("canny::non_max");
+    for span in process {
+        let [above, row, below] = layout.process_rows(span);
+        for k in 0..span.x.len() {
+            let i = row + k + 1;
+            let pixel = g[i];
+            let out = &mut out[i - offset];
+            // flat pixels stay flat whatever their neighbours, so skip finding the direction
+            if pixel <= M::default() {
+                *out = M::default();
+                continue;
+            }
+            let (cmp1, cmp2) = match M::sector(gx[i], gy[i]) {
+                0 => (g[row + k], g[row + k + 2]),
+                1 => (g[below + k + 2], g[above + k]),
+                2 => (g[above + k + 1], g[below + k + 1]),
+                _ => (g[below + k], g[above + k + 2]),
+            };
+
+            // If the pixel is not a local maximum, suppress it.
+            if pixel < cmp1 || pixel < cmp2 {
+                *out = M::default();
+            } else {
+                *out = pixel;
+            }
+        }
+    }
+}
+
+/// Steps to the neighbour along the gradient direction in each `sector`
+const SECTOR_STEPS: [[f32; 2]; 4] = [[1.0, 0.0], [1.0, 1.0], [0.0, 1.0], [-1.0, 1.0]];
+
+/// Which of the four 45° sectors the gradient direction falls in
+///
+/// 0 is horizontal, 1 is down and right, 2 is vertical and 3 is down and left. Opposite
+/// directions share a sector.
+#[inline]
+fn sector(gx: i16, gy: i16) -> usize {
     const RADIANS_TO_DEGREES: f32 = 180_f32 / f32::consts::PI;
-    for [x, y] in window.process() {
-        let i = y * width + x;
-        let x_gradient = gx[i] as f32;
-        let y_gradient = gy[i] as f32;
-        let mut angle = atan2_approx(y_gradient, x_gradient) * RADIANS_TO_DEGREES;
-        if angle < 0.0 {
-            angle += 180.0
-        }
-        let (cmp1, cmp2) = if angle >= 157.5 || angle < 22.5 {
-            (g[y * width + x - 1], g[y * width + x + 1])
-        } else if angle >= 22.5 && angle < 67.5 {
-            (g[(y + 1) * width + x + 1], g[(y - 1) * width + x - 1])
-        } else if angle >= 67.5 && angle < 112.5 {
-            (g[(y - 1) * width + x], g[(y + 1) + x])
-        } else if angle >= 112.5 && angle < 157.5 {
-            (g[(y + 1) * width + x - 1], g[(y - 1) * width + x + 1])
-        } else {
-            unreachable!()
-        };
+    let mut angle = atan2_approx(f32::from(gy), f32::from(gx)) * RADIANS_TO_DEGREES;
+    if angle < 0.0 {
+        angle += 180.0;
+    }
+    if !(22.5..157.5).contains(&angle) {
+        0
+    } else if angle < 67.5 {
+        1
+    } else if angle < 112.5 {
+        2
+    } else {
+        3
+    }
+}
 
-        let pixel = g[y * width + x];
-        // If the pixel is not a local maximum, suppress it.
-        if pixel < cmp1 || pixel < cmp2 {
-            out[i] = BLACK_32;
-        } else {
-            out[i] = pixel;
+/// tan(22.5°) as a fraction of `1 << SECTOR_SCALE_BITS`
+const TAN_22_5: u32 = 13_573;
+
+/// Fixed point scale of `TAN_22_5`
+const SECTOR_SCALE_BITS: u32 = 15;
+
+/// Which of the four 45° sectors the gradient direction falls in, using only integers
+///
+/// The same sectors as `sector`, but with exact boundaries at 22.5° either side of each
+/// direction rather than those of the approximate arctangent.
+#[inline]
+fn integer_sector(gx: i16, gy: i16) -> usize {
+    let (x, y) = (u32::from(gx.unsigned_abs()), u32::from(gy.unsigned_abs()));
+    if y << SECTOR_SCALE_BITS < x * TAN_22_5 {
+        0
+    } else if x << SECTOR_SCALE_BITS <= y * TAN_22_5 {
+        2
+    } else if (gx > 0) == (gy > 0) {
+        1
+    } else {
+        3
+    }
+}
+
+/// Refine the position of each edge by fitting a parabola to the gradient magnitude across it
+///
+/// The magnitudes of the edge and its neighbours either side, in the same direction as
+/// non-maximum suppression compared them, give the offset of the peak along that direction.
+#[allow(clippy::cast_precision_loss)]
+fn sub_pixel<M: Magnitude>(
+    layout: &Layout,
+    g: &[M],
+    gx: &[i16],
+    gy: &[i16],
+    edges: &[u64],
+    out: &mut Vec<[f32; 2]>,
+) {
+    #[cfg(target_arch = "wasm32")]
+    let _timer = performance::Timer::new("canny::sub-pixel");
+    for span in &layout.process {
+        let [above, row, below] = layout.process_rows(span);
+        for (k, x) in span.x.clone().enumerate() {
+            let i = row + k + 1;
+            if !is_edge(edges, i) {
+                continue;
+            }
+            let sector = M::sector(gx[i], gy[i]);
+            let (behind, ahead) = match sector {
+                0 => (g[row + k], g[row + k + 2]),
+                1 => (g[above + k], g[below + k + 2]),
+                2 => (g[above + k + 1], g[below + k + 1]),
+                _ => (g[above + k + 2], g[below + k]),
+            };
+            let (behind, centre, ahead) = (
+                behind.to_f32().sqrt(),
+                g[i].to_f32().sqrt(),
+                ahead.to_f32().sqrt(),
+            );
+            let curvature = behind - 2.0 * centre + ahead;
+            let offset = if curvature < 0.0 {
+                (0.5 * (behind - ahead) / curvature).clamp(-0.5, 0.5)
+            } else {
+                0.0
+            };
+            let [dx, dy] = SECTOR_STEPS[sector];
+            out.push([x as f32 + offset * dx, span.y as f32 + offset * dy]);
         }
     }
 }
 
 /// Filter out edges with the thresholds.
-/// Non-recursive breadth-first search.
-fn hysteresis<T: Window>(
-    width: u32,
-    height: u32,
-    input: &Vec<f32>,
-    out: &mut RgbaImage,
+/// Non-recursive depth-first search.
+///
+/// Only edges on `rows` are followed. The output is a bitset starting with the Point stored at
+/// `offset`.
+#[allow(clippy::too_many_arguments)]
+fn hysteresis<M: Magnitude>(
+    layout: &Layout,
+    process: &[Span],
+    rows: &Range<usize>,
+    input: &[M],
+    out: &mut [u64],
+    offset: usize,
     low_thresh: f32,
     high_thresh: f32,
-    line_colour: Rgba<u8>,
-    window: &T,
 ) {
     #[cfg(target_arch = "wasm32")]
     let _timer = performance::Timer::new("canny::hysteresis");
-    let low_thresh = low_thresh * low_thresh;
-    let high_thresh = high_thresh * high_thresh;
-    let mut edges = Vec::with_capacity((width * height) as usize / 2);
-
-    for [x, y] in window.process() {
-        let (inp_pix, out_pix) = (
-            input[((y * width as usize) + x)],
-            out.get_pixel(x as u32, y as u32),
-        );
-        // If the edge strength is higher than high_thresh, mark it as an edge.
-        if inp_pix >= high_thresh && out_pix[0] != line_colour[0] {
-            out.put_pixel(x as u32, y as u32, line_colour);
-            edges.push((x as u32, y as u32));
-
-            // Track neighbors until no neighbor is >= low_thresh.
-            while !edges.is_empty() {
-                let (nx, ny) = edges.pop().unwrap();
-                let neighbor_indices = [
-                    (nx + 1, ny, nx - 1, ny),
-                    (nx + 1, ny + 1, nx - 1, ny + 1),
-                    (nx, ny + 1, nx + 1, ny),
-                    (nx - 1, ny - 1, nx + 1, ny + 1),
-                    (nx - 1, ny, nx, ny + 1),
-                    (nx - 1, ny + 1, nx - 1, ny - 1),
-                ];
-                // let neighbor_indices = [
-                //     (nx + 1, ny),
-                //     (nx + 1, ny + 1),
-                //     (nx, ny + 1),
-                //     (nx - 1, ny - 1),
-                //     (nx - 1, ny),
-                //     (nx - 1, ny + 1),
-                // ];
-
-                for neighbor_idx in &neighbor_indices {
-                    let (in_neighbor, out_neighbor) = (
-                        input[((neighbor_idx.1 * width) + neighbor_idx.0) as usize],
-                        out.get_pixel(neighbor_idx.0 as u32, neighbor_idx.1 as u32),
-                    );
-                    if in_neighbor >= low_thresh && out_neighbor[0] != line_colour[0] {
-                        out.put_pixel(
-                            neighbor_idx.0 as u32,
-                            neighbor_idx.1 as u32,
-                            line_colour,
-                        );
-                        edges.push((neighbor_idx.0, neighbor_idx.1));
-                    }
+    let low_thresh = M::threshold(low_thresh);
+    let high_thresh = M::threshold(high_thresh);
+    let mut edges = Vec::new();
+
+    for word in out.iter_mut() {
+        *word = 0;
+    }
+
+    for span in process {
+        let [_, row, _] = layout.process_rows(span);
+        for (i, x) in (row + 1..).zip(span.x.clone()) {
+            // If the edge strength is higher than high_thresh, mark it as an edge.
+            // Pixels outside the window have no strength so can never be edges.
+            if input[i] >= high_thresh && input[i] > M::default() && !is_edge(out, i - offset) {
+                set_edge(out, i - offset);
+                edges.push([x, span.y]);
+                follow_edges(layout, rows, input, out, offset, low_thresh, &mut edges);
+            }
+        }
+    }
+}
+
+/// Mark the neighbours of the edges on the stack, and their neighbours, as edges while they are
+/// at least the low threshold.
+///
+/// The threshold is squared like the input. Only edges on `rows` are followed.
+fn follow_edges<M: Magnitude>(
+    layout: &Layout,
+    rows: &Range<usize>,
+    input: &[M],
+    out: &mut [u64],
+    offset: usize,
+    low_thresh: M,
+    edges: &mut Vec<Point>,
+) {
+    // Track neighbors until no neighbor is >= low_thresh.
+    // Only processed Points have any strength so their neighbours are all stored.
+    while let Some([x, y]) = edges.pop() {
+        let neighbor_rows = layout.process_rows(&Span { y, x: x..x + 1 });
+        for (&row, y) in neighbor_rows.iter().zip(y - 1..) {
+            if !rows.contains(&y) {
+                continue;
+            }
+            for (neighbor_idx, x) in (row..).zip(x - 1..=x + 1) {
+                let in_neighbor = input[neighbor_idx];
+                if in_neighbor >= low_thresh
+                    && in_neighbor > M::default()
+                    && !is_edge(out, neighbor_idx - offset)
+                {
+                    set_edge(out, neighbor_idx - offset);
+                    edges.push([x, y]);
                 }
             }
         }
     }
 }
 
-#[allow(clippy::similar_names)]
-fn gradient<T: Window>(
+#[inline]
+fn is_edge(edges: &[u64], i: usize) -> bool {
+    edges[i / 64] & (1 << (i % 64)) != 0
+}
+
+#[inline]
+fn set_edge(edges: &mut [u64], i: usize) {
+    edges[i / 64] |= 1 << (i % 64);
+}
+
+/// Running totals for `gradient`, allocated once for the widest span it will calculate
+struct GradientScratch {
+    hacc: Vec<i32>,
+    vacc: Vec<i32>,
+}
+
+impl GradientScratch {
+    fn new(widest: usize) -> Self {
+        Self {
+            hacc: vec![0; widest],
+            vacc: vec![0; widest],
+        }
+    }
+}
+
+/// Calculate the gradient of the Points in `spans` from the pixels stored by `source`
+#[allow(clippy::similar_names, clippy::too_many_arguments)]
+fn gradient<M: Magnitude>(
     width: usize,
     height: usize,
+    source: &Layout,
     image: &[u8],
-    hout: &mut Vec<i16>,
-    vout: &mut Vec<i16>,
-    out: &mut Vec<f32>,
-    window: &T,
+    spans: &[(Span, usize)],
+    hout: &mut [i16],
+    vout: &mut [i16],
+    out: &mut [M],
+    scratch: &mut GradientScratch,
+    kernel: GradientKernel,
+    border: BorderMode,
 ) {
     #[cfg(target_arch = "wasm32")]
     let _timer = performance::Timer::new("canny::gradient");
-    let (k_width, k_height) = (3, 3);
-
-    let mut hacc = 0_i32;
-    let mut vacc = 0_i32;
-
-    for [x, y] in window.gradient() {
-        // inner loops
-        for k_y in 0..k_height {
-            let y_p = min(
-                height + height - 1,
-                max(height, height + y + k_y - k_height / 2),
-            ) - height; // min(height - 1, max(0, y + k_y - k_height / 2))
-            for k_x in 0..k_width {
-                let x_p =
-                    min(width + width - 1, max(width, width + x + k_x - k_width / 2)) - width;
-
-                let (p, hk, vk) = (
-                    image[y_p * width + x_p],
-                    HORIZONTAL_SOBEL[k_y * k_width + k_x],
-                    VERTICAL_SOBEL[k_y * k_width + k_x],
-                );
-                hacc = accumulate(hacc, p, hk);
-                vacc = accumulate(vacc, p, vk);
-            } // end k_width loop
-        } // end k_height loop
+    #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+    use crate::simd::accumulate_taps;
+    let k_size = kernel.size();
+    let radius = k_size / 2;
+    let (horizontal, vertical) = (kernel.horizontal(), kernel.vertical());
+    // index of the leftmost pixel under the kernel in each row, if the row has one
+    let mut rows = [None; 5];
+    let rows = &mut rows[..k_size];
+    // running totals for the Points of a span whose kernel is entirely inside the image
+    let GradientScratch { hacc, vacc } = scratch;
+    // the outputs start with the first span
+    let offset = spans.first().map_or(0, |(_, start)| *start);
+
+    for (Span { y, x: span }, start) in spans {
+        let left = span.start.saturating_sub(radius);
+        for (k_y, row) in rows.iter_mut().enumerate() {
+            *row = border
+                .index(y + k_y, radius, height)
+                .map(|row| source_index(source, left, row));
+        }
+        let index = |x: usize| start - offset + x - span.start;
+        if border == BorderMode::Skip && rows.contains(&None) {
+            for x in span.clone() {
+                write_gradient(index(x), 0, 0, hout, vout, out);
+            }
+            continue;
+        }
+
+        // Points near the left and right of the image need their taps handled by the border mode
+        let inner = max(span.start, radius)..min(span.end, width.saturating_sub(radius));
+        let inner = inner.start..max(inner.start, inner.end);
+        for x in (span.start..inner.start).chain(inner.end..span.end) {
+            let (mut h, mut v) = (0_i32, 0_i32);
+            if border != BorderMode::Skip {
+                for (k_y, row) in rows.iter().enumerate() {
+                    for k_x in 0..k_size {
+                        if let (Some(row), Some(x_p)) = (row, border.index(x + k_x, radius, width))
+                        {
+                            let p = image[row + x_p - left];
+                            h = accumulate(h, p, horizontal[k_y * k_size + k_x]);
+                            v = accumulate(v, p, vertical[k_y * k_size + k_x]);
+                        }
+                    }
+                }
+            }
+            write_gradient(index(x), clamp(h), clamp(v), hout, vout, out);
+        }
+
+        // the rest of the span is done a kernel tap at a time over contiguous pixels
+        let n = inner.len();
+        let (hacc, vacc) = (&mut hacc[..n], &mut vacc[..n]);
+        hacc.fill(0);
+        vacc.fill(0);
+        for (k_y, row) in rows.iter().enumerate() {
+            let Some(row) = row else { continue };
+            for k_x in 0..k_size {
+                let start = row + inner.start + k_x - radius - left;
+                let pixels = &image[start..start + n];
+                let (hk, vk) = (horizontal[k_y * k_size + k_x], vertical[k_y * k_size + k_x]);
+                if hk != 0 {
+                    accumulate_taps(hacc, pixels, hk);
+                }
+                if vk != 0 {
+                    accumulate_taps(vacc, pixels, vk);
+                }
+            }
+        }
+        let i = index(inner.start);
+        M::store_gradients(
+            hacc,
+            vacc,
+            &mut hout[i..i + n],
+            &mut vout[i..i + n],
+            &mut out[i..i + n],
+        );
+    }
+}
+
+/// Add a kernel tap to the running totals of a row of Points
+#[cfg_attr(
+    all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"),
+    allow(dead_code)
+)]
+pub(crate) fn accumulate_taps(acc: &mut [i32], pixels: &[u8], weight: i32) {
+    for (acc, &p) in acc.iter_mut().zip(pixels) {
+        *acc = accumulate(*acc, p, weight);
+    }
+}
 
-        let h = clamp(hacc);
-        let v = clamp(vacc);
-        let p = (h as f32) * (h as f32) + (v as f32) * (v as f32);
-        hacc = 0_i32;
-        vacc = 0_i32;
-        let i = y * width + x;
-        hout[i] = h;
-        vout[i] = v;
-        // TODO: out == h^2 + v^2 so do them all at the end?
-        out[i] = p;
+/// Store the gradients of a row of Points from their running totals
+pub(crate) fn store_gradients<M: Magnitude>(hacc: &[i32], vacc: &[i32], hout: &mut [i16], vout: &mut [i16], out: &mut [M]) {
+    for (i, (&h, &v)) in hacc.iter().zip(vacc).enumerate() {
+        write_gradient(i, clamp(h), clamp(v), hout, vout, out);
     }
 }
 
+/// Store the gradient of one Point along with its squared magnitude
+#[inline]
+fn write_gradient<M: Magnitude>(i: usize, h: i16, v: i16, hout: &mut [i16], vout: &mut [i16], out: &mut [M]) {
+    hout[i] = h;
+    vout[i] = v;
+    // TODO: out == h^2 + v^2 so do them all at the end?
+    out[i] = M::squared(h, v);
+}
+
+/// Index of a pixel that must be stored by the source layout
+#[inline]
+fn source_index(source: &Layout, x: usize, y: usize) -> usize {
+    source
+        .index(x, y)
+        .expect("pixels under the kernels are stored")
+}
+
+/// Clamp `i - offset` to a valid index of something `len` long
+///
+/// The offset is passed separately so that indices before the start don't underflow.
+#[inline]
+fn clamp_index(i: usize, offset: usize, len: usize) -> usize {
+    min(i.saturating_sub(offset), len - 1)
+}
+
 #[inline]
 fn clamp(x: i32) -> i16 {
     if x < i16::MAX as i32 {
@@ -532,9 +2576,14 @@ fn atan2_approx(y: f32, x: f32) -> f32 {
 mod tests {
     extern crate test;
 
-    use super::{gradient, CannyBuilder, RectangleWindow, RectangleInRectangleWindow, Window};
+    use super::{
+        blur, gaussian_kernel, gradient, integer_sector, sector, select_thresholds, Arithmetic,
+        AutoThreshold, BlurScratch, BorderMode, CannyBuilder, ColourGradient, GradientKernel,
+        GradientScratch, Layout, Magnitudes, MaskWindow, PolygonWindow, RectangleWindow,
+        RectangleInRectangleWindow, Span, Window, GAUSSIAN_SCALE_BITS, HISTOGRAM_BINS,
+    };
     use crate::data::{Rectangle, Point};
-    use image;
+    use image::{self, GrayImage, Luma, Rgba, RgbaImage};
     use test::Bencher;
 
     #[test]
@@ -548,7 +2597,297 @@ mod tests {
             rectangle: Rectangle([[0, 0], [width - 1, height - 1]]),
         };
 
-        gradient(width, height, &image, &mut hout, &mut vout, &mut out, &window);
+        let layout = Layout::new(&window);
+        gradient(
+            width,
+            height,
+            &layout.padded(1, width, height),
+            &image,
+            &layout.spans,
+            &mut hout,
+            &mut vout,
+            &mut out,
+            &mut GradientScratch::new(width),
+            GradientKernel::Sobel,
+            BorderMode::Replicate,
+        );
+    }
+
+    #[test]
+    fn test_gradient_kernels() {
+        let (width, height) = (10, 10);
+        let window = RectangleWindow {
+            rectangle: Rectangle([[0, 0], [width, height]]),
+        };
+        // vertical step edge between x = 4 and x = 5
+        let image: Vec<u8> = (0..width * height)
+            .map(|i| if i % width < 5 { 0 } else { 100 })
+            .collect();
+
+        for &kernel in &[
+            GradientKernel::Sobel,
+            GradientKernel::Scharr,
+            GradientKernel::Prewitt,
+            GradientKernel::Sobel5,
+        ] {
+            let size = kernel.size();
+            assert_eq!(kernel.horizontal().len(), size * size);
+            assert_eq!(kernel.horizontal().iter().sum::<i32>(), 0);
+            for y in 0..size {
+                for x in 0..size {
+                    assert_eq!(
+                        kernel.horizontal()[y * size + x],
+                        kernel.vertical()[x * size + y]
+                    );
+                }
+            }
+
+            let mut hout = vec![0; width * height];
+            let mut vout = vec![0; width * height];
+            let mut out = vec![0_f32; width * height];
+            let layout = Layout::new(&window);
+            let spans = &layout.spans;
+            let border = BorderMode::Replicate;
+            let scratch = &mut GradientScratch::new(width);
+            gradient(
+                width, height, &layout, &image, spans, &mut hout, &mut vout, &mut out, scratch,
+                kernel, border,
+            );
+
+            let i = 5 * width + 5;
+            assert!(hout[i] > 0, "{:?}", kernel);
+            assert_eq!(vout[i], 0, "{:?}", kernel);
+            assert_eq!(hout[5 * width + 1], 0, "{:?}", kernel);
+        }
+    }
+
+    /// Sobel gradient of a horizontal ramp rising from 100 by 10 a column
+    fn ramp_gradient(border: BorderMode) -> impl Fn(usize, usize) -> (i16, i16, f32) {
+        let (width, height) = (10, 10);
+        #[allow(clippy::cast_possible_truncation)]
+        let image: Vec<u8> = (0..width * height)
+            .map(|i| 100 + 10 * (i % width) as u8)
+            .collect();
+        let window = RectangleWindow::new(Rectangle([[0, 0], [width, height]]));
+        let layout = Layout::new(&window);
+        let mut hout = vec![0; layout.len];
+        let mut vout = vec![0; layout.len];
+        let mut out = vec![0_f32; layout.len];
+        let kernel = GradientKernel::Sobel;
+        let spans = &layout.spans;
+        let scratch = &mut GradientScratch::new(width);
+        gradient(
+            width, height, &layout, &image, spans, &mut hout, &mut vout, &mut out, scratch, kernel,
+            border,
+        );
+        move |x, y| {
+            let i = layout.index(x, y).unwrap();
+            (hout[i], vout[i], out[i])
+        }
+    }
+
+    #[test]
+    fn test_border_replicate() {
+        let gradient = ramp_gradient(BorderMode::Replicate);
+        assert_eq!(gradient(5, 5), (80, 0, 6400.0));
+        assert_eq!(gradient(0, 5), (40, 0, 1600.0));
+        assert_eq!(gradient(9, 5), (40, 0, 1600.0));
+        assert_eq!(gradient(5, 0), (80, 0, 6400.0));
+    }
+
+    #[test]
+    fn test_border_reflect() {
+        let gradient = ramp_gradient(BorderMode::Reflect);
+        assert_eq!(gradient(5, 5), (80, 0, 6400.0));
+        // the ramp is mirrored into a peak or trough at the sides
+        assert_eq!(gradient(0, 5), (0, 0, 0.0));
+        assert_eq!(gradient(9, 5), (0, 0, 0.0));
+        assert_eq!(gradient(5, 0), (80, 0, 6400.0));
+        assert_eq!(gradient(5, 9), (80, 0, 6400.0));
+    }
+
+    #[test]
+    fn test_border_zero() {
+        let gradient = ramp_gradient(BorderMode::Zero);
+        assert_eq!(gradient(5, 5), (80, 0, 6400.0));
+        assert_eq!(gradient(0, 5), (440, 0, 440.0 * 440.0));
+        // a strong edge against the black above the image
+        assert_eq!(gradient(5, 0), (60, 600, 60.0 * 60.0 + 600.0 * 600.0));
+        assert_eq!(gradient(5, 9), (60, -600, 60.0 * 60.0 + 600.0 * 600.0));
+    }
+
+    #[test]
+    fn test_border_skip() {
+        let gradient = ramp_gradient(BorderMode::Skip);
+        assert_eq!(gradient(5, 5), (80, 0, 6400.0));
+        assert_eq!(gradient(1, 1), (80, 0, 6400.0));
+        assert_eq!(gradient(0, 5), (0, 0, 0.0));
+        assert_eq!(gradient(9, 5), (0, 0, 0.0));
+        assert_eq!(gradient(5, 0), (0, 0, 0.0));
+        assert_eq!(gradient(5, 9), (0, 0, 0.0));
+    }
+
+    #[test]
+    fn test_gaussian_kernel() {
+        assert!(gaussian_kernel(0.0).is_empty());
+        assert!(gaussian_kernel(0.1).is_empty());
+
+        let kernel = gaussian_kernel(1.4);
+        assert_eq!(kernel.len(), 11);
+        assert_eq!(kernel.iter().sum::<u32>(), 1 << GAUSSIAN_SCALE_BITS);
+        assert_eq!(kernel.iter().max(), Some(&kernel[5]));
+        assert_eq!(kernel[4], kernel[6]);
+    }
+
+    #[test]
+    fn test_blur() {
+        let (width, height) = (10, 10);
+        let window = RectangleWindow {
+            rectangle: Rectangle([[2, 2], [8, 8]]),
+        };
+        let kernel = gaussian_kernel(1.0);
+        let layout = Layout::new(&window);
+        let source = Layout::new(&RectangleWindow::new(Rectangle([[0, 0], [width, height]])));
+        let border = BorderMode::Replicate;
+
+        let mut out = vec![0; layout.len];
+        let mut scratch = BlurScratch::new(&kernel, &layout);
+        let mut smooth = |image: &mut [u8]| {
+            blur(width, height, &source, image, &layout, &mut out, &mut scratch, &kernel, border);
+        };
+
+        // flat regions stay flat
+        let mut image = vec![100; width * height];
+        smooth(&mut image);
+        assert!(image.iter().all(|&p| p == 100));
+
+        // a single bright pixel is spread over its neighbours within the window only
+        let mut image = vec![0; width * height];
+        image[5 * width + 5] = 255;
+        smooth(&mut image);
+        assert!(image[5 * width + 5] < 255);
+        assert!(image[5 * width + 4] > 0);
+        assert_eq!(image[5 * width + 4], image[5 * width + 6]);
+        assert_eq!(image[5 * width + 8], 0);
+    }
+
+    #[test]
+    fn test_blur_border() {
+        let (width, height) = (10, 10);
+        let window = RectangleWindow {
+            rectangle: Rectangle([[0, 0], [10, 10]]),
+        };
+        let kernel = gaussian_kernel(1.0);
+        let layout = Layout::new(&window);
+        let mut out = vec![0; layout.len];
+        let mut scratch = BlurScratch::new(&kernel, &layout);
+        let mut blurred = |border, mut image: Vec<u8>| {
+            let out = &mut out;
+            blur(width, height, &layout, &mut image, &layout, out, &mut scratch, &kernel, border);
+            image
+        };
+        let flat = vec![100; width * height];
+
+        // replicated and reflected borders keep a flat image flat
+        assert!(blurred(BorderMode::Replicate, flat.clone()).iter().all(|&p| p == 100));
+        assert!(blurred(BorderMode::Reflect, flat.clone()).iter().all(|&p| p == 100));
+
+        // a zero border darkens the edge of the image
+        let image = blurred(BorderMode::Zero, flat);
+        assert!(image[0] < image[1]);
+        assert!(image[width + 1] < 100);
+        assert_eq!(image[5 * width + 5], 100);
+
+        // skipping the border leaves the Points whose kernel doesn't fit unblurred
+        let mut image = vec![0; width * height];
+        image[5] = 255;
+        image[5 * width + 5] = 255;
+        let image = blurred(BorderMode::Skip, image);
+        assert_eq!(image[5], 255);
+        assert_eq!(image[4], 0);
+        assert!(image[5 * width + 5] < 255);
+        assert!(image[5 * width + 4] > 0);
+    }
+
+    #[test]
+    fn test_select_thresholds() {
+        let (width, height) = (20, 20);
+        let window = RectangleWindow {
+            rectangle: Rectangle([[0, 0], [width, height]]),
+        };
+        let layout = Layout::new(&window);
+        let mut histogram = vec![0; HISTOGRAM_BINS];
+        // squared magnitudes, three quarters weak noise and a quarter strong edges
+        let g: Vec<f32> = (0..width * height)
+            .map(|i| if i % 4 == 0 { 200.0 * 200.0 } else { 20.0 * 20.0 })
+            .collect();
+
+        let (low, high) = select_thresholds(
+            &layout,
+            &g,
+            &mut histogram,
+            AutoThreshold::Median { low: 1.0, high: 2.0 },
+            &window,
+        )
+        .unwrap();
+        assert!((low - 20.0).abs() < 1.0, "{}", low);
+        assert!((high - 40.0).abs() < 2.0, "{}", high);
+
+        let (low, high) = select_thresholds(
+            &layout,
+            &g,
+            &mut histogram,
+            AutoThreshold::Otsu { ratio: 0.5 },
+            &window,
+        )
+        .unwrap();
+        // the split falls at the top of the weak noise's bin, 20 / (200 / 255) = 25.5
+        assert!((high - 26.0 * 200.0 / 255.0).abs() < 1e-4, "{}", high);
+        assert!((low - high / 2.0).abs() < f32::EPSILON);
+
+        // a flat image has no edges to threshold
+        let g = vec![0.0; width * height];
+        let thresholds = select_thresholds(
+            &layout,
+            &g,
+            &mut histogram,
+            AutoThreshold::Otsu { ratio: 0.5 },
+            &window,
+        );
+        assert_eq!(thresholds, None);
+    }
+
+    #[test]
+    fn test_auto_threshold_blank() {
+        let (width, height) = (20, 20);
+        let mut canny = CannyBuilder::new(width as usize, height as usize)
+            .low_threshold(40.0)
+            .high_threshold(80.0)
+            .auto_threshold(AutoThreshold::Otsu { ratio: 0.5 })
+            .build();
+
+        // a blank frame keeps the configured thresholds and has no edges
+        let blank = RgbaImage::from_pixel(width, height, Rgba([128, 128, 128, 255]));
+        let mut img = blank.clone();
+        canny.detect(&mut img);
+        assert_eq!(img, blank);
+        assert_eq!(canny.thresholds(), (40.0, 80.0));
+
+        // a blank frame after a real one keeps the thresholds chosen for the real one
+        let mut square = RgbaImage::from_fn(width, height, |x, y| {
+            if (5..15).contains(&x) && (5..15).contains(&y) {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+        canny.detect(&mut square);
+        let thresholds = canny.thresholds();
+        assert_ne!(thresholds, (40.0, 80.0));
+        let mut img = blank.clone();
+        canny.detect(&mut img);
+        assert_eq!(img, blank);
+        assert_eq!(canny.thresholds(), thresholds);
     }
 
     #[test]
@@ -574,6 +2913,494 @@ mod tests {
         assert_eq!(points.len(), 84);
     }
 
+    #[test]
+    fn test_layout() {
+        let window = RectangleInRectangleWindow {
+            outer: Rectangle([[10, 20], [20, 30]]),
+            inner: Rectangle([[12, 22], [16, 26]]),
+        };
+        let layout = Layout::new(&window);
+        assert_eq!(layout.len, 84);
+        for (i, point) in window.gradient().enumerate() {
+            assert_eq!(layout.index(point[0], point[1]), Some(i));
+            assert_eq!(layout.point(i), point);
+        }
+        assert_eq!(layout.index(13, 23), None);
+        assert_eq!(layout.index(9, 25), None);
+        assert_eq!(layout.index(15, 30), None);
+        assert_eq!(layout.index(15, 0), None);
+        assert_eq!(layout.process_rows(&Span { y: 21, x: 11..19 }), [0, 10, 20]);
+
+        // the pixels under the kernels grow the frame outwards and into the hole
+        let padded = layout.padded(1, 640, 480);
+        assert_eq!(padded.len, 140);
+        assert_eq!(padded.rows(), 19..31);
+        assert_eq!(padded.row(23).len(), 2);
+        assert_eq!(padded.index(9, 19), Some(0));
+        assert_eq!(padded.index(13, 23), None);
+        // and are clipped to the image
+        let padded = layout.padded(15, 25, 480);
+        assert_eq!(padded.rows(), 5..45);
+        assert_eq!(padded.row(25), &[(Span { y: 25, x: 0..25 }, 20 * 25)]);
+
+        // buffers are sized by the window rather than the image
+        let mut canny = CannyBuilder::with_window(640, 480, window).build();
+        assert_eq!(canny.plane.len(), 140);
+        assert_eq!(canny.suppressed, Magnitudes::Float(vec![0.0; 84]));
+        let edges = canny.detect_edges(&RgbaImage::new(640, 480));
+        assert_eq!(edges.magnitude, Magnitudes::Float(vec![0.0; 84]));
+        assert!(!edges.is_edge(300, 300));
+        assert!(edges.magnitude(300, 300) < f32::EPSILON);
+    }
+
+    /// Expand Spans back into the Points they cover
+    fn span_points(spans: impl Iterator<Item = Span>) -> Vec<Point> {
+        spans.flat_map(|Span { y, x }| x.map(move |x| [x, y])).collect()
+    }
+
+    /// Check a window's Spans cover the same Points as its Point iterators
+    fn assert_spans_match<W: Window>(window: &W) {
+        assert_eq!(span_points(window.gradient_spans()), window.gradient().collect::<Vec<_>>());
+        assert_eq!(span_points(window.process_spans()), window.process().collect::<Vec<_>>());
+        assert!(window.gradient_spans().all(|span| !span.x.is_empty()));
+    }
+
+    #[test]
+    fn test_spans() {
+        let rectangle = RectangleWindow::new(Rectangle([[2, 1], [8, 4]]));
+        assert_eq!(
+            rectangle.gradient_spans().collect::<Vec<Span>>(),
+            vec![
+                Span { y: 1, x: 2..8 },
+                Span { y: 2, x: 2..8 },
+                Span { y: 3, x: 2..8 },
+            ]
+        );
+        assert_spans_match(&rectangle);
+
+        let frame = RectangleInRectangleWindow {
+            outer: Rectangle([[0, 0], [10, 10]]),
+            inner: Rectangle([[2, 2], [6, 6]]),
+        };
+        let spans = frame.gradient_spans().collect::<Vec<Span>>();
+        assert_eq!(spans.len(), 2 + 4 * 2 + 4);
+        assert_eq!(spans[2], Span { y: 2, x: 0..2 });
+        assert_eq!(spans[3], Span { y: 2, x: 6..10 });
+        assert_spans_match(&frame);
+
+        // the grown hole meets the shrunk outline leaving only one side of some rows
+        let thin = RectangleInRectangleWindow {
+            outer: Rectangle([[0, 0], [10, 10]]),
+            inner: Rectangle([[1, 2], [7, 8]]),
+        };
+        assert_eq!(
+            thin.process_spans()
+                .filter(|span| span.y == 4)
+                .collect::<Vec<Span>>(),
+            vec![Span { y: 4, x: 8..9 }]
+        );
+        assert_spans_match(&thin);
+
+        let mask = MaskWindow::from_fn(12, 12, |x, y| (x + y) % 5 != 0);
+        assert_spans_match(&mask);
+        let polygon = PolygonWindow::new(12, 12, &[[1.0, 1.0], [11.0, 3.0], [4.0, 11.0]]);
+        assert_spans_match(&polygon);
+        assert_spans_match(&frame.union(rectangle).difference(mask));
+    }
+
+    #[test]
+    fn test_horizontal_edge_thickness() {
+        let (width, height) = (20, 20);
+        // dark above a light band, with the steepest change at row 10
+        let img = RgbaImage::from_fn(width, height, |_, y| match y {
+            0..=9 => Rgba([0, 0, 0, 255]),
+            10 => Rgba([128, 128, 128, 255]),
+            _ => Rgba([255, 255, 255, 255]),
+        });
+        let mut canny = CannyBuilder::new(width as usize, height as usize).build();
+
+        let edges = canny.detect_edges(&img);
+
+        // non-maximum suppression compares with the rows above and below so the edge is one
+        // pixel thick
+        let points = edges.points().collect::<Vec<Point>>();
+        assert!(!points.is_empty());
+        assert!(points.iter().all(|&[_, y]| y == 10), "{:?}", points);
+    }
+
+    #[test]
+    fn test_detect_edges() {
+        let (width, height) = (20, 20);
+        // black square on a white background
+        let img = RgbaImage::from_fn(width, height, |x, y| {
+            if (5..15).contains(&x) && (5..15).contains(&y) {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+        let mut canny = CannyBuilder::new(width as usize, height as usize).build();
+
+        let edges = canny.detect_edges(&img);
+
+        assert!(edges.is_edge(5, 10) || edges.is_edge(4, 10));
+        assert!(edges.is_edge(10, 5) || edges.is_edge(10, 4));
+        // black pixels are not edges just because of their colour
+        assert!(!edges.is_edge(10, 10));
+        assert!(!edges.is_edge(1, 1));
+        assert!(edges.magnitude(10, 10) < f32::EPSILON);
+        assert!(edges.magnitude(5, 10) > 0.0);
+        // gradient points from dark to light
+        assert!(edges.direction(5, 10).abs() > 3.0);
+
+        let points = edges.points().collect::<Vec<Point>>();
+        assert!(!points.is_empty());
+        assert!(points.iter().all(|&[x, y]| edges.is_edge(x, y)));
+        assert!(points.windows(2).all(|p| (p[0][1], p[0][0]) < (p[1][1], p[1][0])));
+
+        // the input is left untouched
+        assert!(img.pixels().all(|p| p[0] == 0 || p[0] == 255));
+
+        let mut rendered = img.clone();
+        edges.render(&mut rendered, Rgba([255, 0, 0, 255]));
+        assert_eq!(
+            rendered.pixels().filter(|&&p| p == Rgba([255, 0, 0, 255])).count(),
+            points.len()
+        );
+    }
+
+    #[test]
+    fn test_gradient_field() {
+        let (width, height) = (30, 30);
+        // black square on a white background
+        let img = RgbaImage::from_fn(width, height, |x, y| {
+            if (8..22).contains(&x) && (8..22).contains(&y) {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+        let window = RectangleInRectangleWindow::new(
+            Rectangle([[4, 4], [26, 26]]),
+            Rectangle([[12, 12], [18, 18]]),
+        );
+        let mut canny = CannyBuilder::with_window(width as usize, height as usize, window).build();
+        let points = canny.detect_edges(&img).points().collect::<Vec<Point>>();
+        assert!(!points.is_empty());
+        let field = canny.gradient_field();
+        assert_eq!((field.width(), field.height()), (30, 30));
+
+        // outside the window and in the hole there is no gradient
+        for &[x, y] in &[[1, 1], [15, 15], [28, 10]] {
+            assert!(!field.contains(x, y));
+            assert_eq!(field.gradient(x, y), [0, 0]);
+            assert!(field.magnitude(x, y) < f32::EPSILON);
+        }
+
+        // the left edge of the square gets darker to the right
+        assert!(field.contains(8, 15) || field.contains(7, 15));
+        let [gx, gy] = field.gradient(8, 15);
+        assert!(gx < 0 && gy == 0);
+        assert!((field.magnitude(8, 15) - f32::from(-gx)).abs() < f32::EPSILON);
+        assert!((field.orientation(8, 15).abs() - std::f32::consts::PI).abs() < 1e-4);
+        assert!(field.suppressed(8, 15) > 0.0 || field.suppressed(7, 15) > 0.0);
+        assert!(field.suppressed(6, 15) < f32::EPSILON);
+
+        // edges are only found where the suppressed magnitude is above the low threshold
+        assert!(points.iter().all(|&[x, y]| field.suppressed(x, y) >= 150.0));
+
+        let mut count = 0;
+        for row in field.spans() {
+            let Span { y, x } = row.span.clone();
+            for (k, x) in x.enumerate() {
+                assert_eq!(field.gradient(x, y), [row.gx[k], row.gy[k]]);
+                assert!((field.magnitude(x, y) - row.magnitude(x)).abs() < f32::EPSILON);
+                assert!((field.suppressed(x, y) - row.suppressed(x)).abs() < f32::EPSILON);
+                count += 1;
+            }
+        }
+        assert_eq!(count, canny.edges.layout.len);
+    }
+
+    #[test]
+    fn test_sub_pixel() {
+        let (width, height) = (24, 16);
+        // a blurred vertical edge whose midpoint is between x = 10 and x = 11, closer to 10
+        let img = RgbaImage::from_fn(width, height, |x, _| match x {
+            0..=9 => Rgba([0, 0, 0, 255]),
+            10 => Rgba([100, 100, 100, 255]),
+            _ => Rgba([255, 255, 255, 255]),
+        });
+        let mut canny = CannyBuilder::new(width as usize, height as usize).build();
+        assert!(canny.detect_edges(&img).sub_pixel_points().is_empty());
+
+        let mut canny = CannyBuilder::new(width as usize, height as usize)
+            .sub_pixel(true)
+            .build();
+        let edges = canny.detect_edges(&img);
+        let points = edges.points().collect::<Vec<Point>>();
+        let refined = edges.sub_pixel_points();
+        assert_eq!(refined.len(), points.len());
+        for (&[x, y], &[refined_x, refined_y]) in points.iter().zip(refined) {
+            assert_eq!(x, 10);
+            assert!((refined_y - y as f32).abs() < f32::EPSILON);
+            assert!(refined_x > 10.05 && refined_x < 10.25, "{}", refined_x);
+        }
+
+        // edges either side of a sharp diagonal edge are both pulled towards it
+        let img = RgbaImage::from_fn(width, height, |x, y| {
+            if x + y < 20 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+        let edges = canny.detect_edges(&img);
+        let points = edges.points().collect::<Vec<Point>>();
+        for (&[x, y], &[refined_x, refined_y]) in points.iter().zip(edges.sub_pixel_points()) {
+            if (4..12).contains(&y) {
+                let distance = ((x + y) as f32 - 19.5).abs();
+                let refined_distance = (refined_x + refined_y - 19.5).abs();
+                assert!(refined_distance < distance, "{} {}", refined_x, refined_y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_integer_sector() {
+        let boundaries = [22.5, 67.5, 112.5, 157.5];
+        for gx in -60..=60_i16 {
+            for gy in -60..=60_i16 {
+                let mut angle = f32::from(gy).atan2(f32::from(gx)).to_degrees();
+                if angle < 0.0 {
+                    angle += 180.0;
+                }
+                // the approximate arctangent moves the boundaries slightly
+                if (gx, gy) == (0, 0) || boundaries.iter().any(|b| (angle - b).abs() < 0.5) {
+                    continue;
+                }
+                assert_eq!(integer_sector(gx, gy), sector(gx, gy), "{} {}", gx, gy);
+            }
+        }
+        assert_eq!(integer_sector(i16::MIN, i16::MIN), 1);
+        assert_eq!(integer_sector(i16::MAX, i16::MIN), 3);
+        assert_eq!(integer_sector(i16::MIN, 0), 0);
+        assert_eq!(integer_sector(0, i16::MAX), 2);
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        let img = image::open("test_images/uno-7.jpg").unwrap().to_rgba();
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let detect = |arithmetic| {
+            let mut canny = CannyBuilder::new(width, height)
+                .low_threshold(50.0)
+                .high_threshold(100.0)
+                .arithmetic(arithmetic)
+                .build();
+            let edges = canny.detect_edges(&img);
+            let points = edges.points().collect::<Vec<Point>>();
+            let magnitudes = points
+                .iter()
+                .map(|&[x, y]| edges.magnitude(x, y))
+                .collect::<Vec<f32>>();
+            (points, magnitudes)
+        };
+        let (float, _) = detect(Arithmetic::Float);
+        let (integer, magnitudes) = detect(Arithmetic::Integer);
+        assert!(!integer.is_empty());
+
+        // the magnitudes are the same, only the directions on the sector boundaries differ
+        let mut canny = CannyBuilder::new(width, height).build();
+        let edges = canny.detect_edges(&img);
+        for (&[x, y], &magnitude) in integer.iter().zip(&magnitudes) {
+            assert!((edges.magnitude(x, y) - magnitude).abs() < f32::EPSILON);
+        }
+        let differences = integer.iter().filter(|point| !float.contains(point)).count()
+            + float.iter().filter(|point| !integer.contains(point)).count();
+        assert!(differences * 50 < float.len(), "{} of {}", differences, float.len());
+    }
+
+    #[test]
+    fn test_colour_gradient() {
+        let (width, height) = (40, 40);
+        // red against brown, which have almost the same luma
+        let img = RgbaImage::from_fn(width, height, |x, _| {
+            if x < 20 {
+                Rgba([200, 30, 30, 255])
+            } else {
+                Rgba([100, 60, 30, 255])
+            }
+        });
+        let detect = |colour_gradient| {
+            let mut canny = CannyBuilder::new(width as usize, height as usize)
+                .low_threshold(50.0)
+                .high_threshold(100.0)
+                .colour_gradient(colour_gradient)
+                .build();
+            let edges = canny.detect_edges(&img);
+            (
+                edges.is_edge(19, 20) || edges.is_edge(20, 20),
+                edges.magnitude(20, 20),
+            )
+        };
+
+        assert!(!detect(ColourGradient::Luma).0);
+        let (edge, magnitude) = detect(ColourGradient::MaxChannel);
+        assert!(edge);
+        assert!((magnitude - 400.0).abs() < 1.0, "{}", magnitude);
+        let (edge, magnitude) = detect(ColourGradient::DiZenzo);
+        assert!(edge);
+        // the changes in red and green add up across the channels
+        assert!(magnitude > 400.0 / 3_f32.sqrt(), "{}", magnitude);
+
+        // with no colour every mode finds the same edges
+        let img = RgbaImage::from_fn(width, height, |x, y| {
+            if (10..30).contains(&x) && (12..25).contains(&y) {
+                Rgba([40, 40, 40, 255])
+            } else {
+                Rgba([220, 220, 220, 255])
+            }
+        });
+        let mut luma = CannyBuilder::new(width as usize, height as usize).build();
+        let luma = luma.detect_edges(&img);
+        for &colour_gradient in &[ColourGradient::MaxChannel, ColourGradient::DiZenzo] {
+            let mut canny = CannyBuilder::new(width as usize, height as usize)
+                .colour_gradient(colour_gradient)
+                .build();
+            let edges = canny.detect_edges(&img);
+            assert!(edges.points().eq(luma.points()), "{:?}", colour_gradient);
+            for y in 0..height as usize {
+                for x in 0..width as usize {
+                    let (magnitude, expected) = (edges.magnitude(x, y), luma.magnitude(x, y));
+                    assert!((magnitude - expected).abs() <= expected * 0.01);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_mask_window() {
+        // a plus sign, three pixels thick
+        let window = MaskWindow::from_fn(10, 10, |x, y| {
+            (3..6).contains(&x) || (3..6).contains(&y)
+        });
+
+        let points = window.gradient().collect::<Vec<Point>>();
+        assert_eq!(points.len(), 51);
+        assert!(points.windows(2).all(|p| (p[0][1], p[0][0]) < (p[1][1], p[1][0])));
+
+        // only the Points with all their neighbours in the mask, away from the image border
+        let points = window.process().collect::<Vec<Point>>();
+        assert_eq!(points.len(), 15);
+        assert!(points.contains(&[4, 4]));
+        assert!(points.contains(&[4, 1]));
+        assert!(!points.contains(&[4, 0]));
+        assert!(!points.contains(&[3, 1]));
+        assert!(!points.contains(&[3, 3]));
+
+        let mut image = GrayImage::new(10, 10);
+        image.put_pixel(2, 3, Luma([255]));
+        let window = MaskWindow::from_image(&image);
+        assert_eq!(window.gradient().collect::<Vec<Point>>(), vec![[2, 3]]);
+        assert_eq!(window.process().count(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_mask_window_larger_than_image() {
+        let window = MaskWindow::from_fn(20, 10, |x, _| x > 12);
+        CannyBuilder::with_window(10, 10, window).build();
+    }
+
+    #[test]
+    fn test_polygon_window() {
+        // concave L shape
+        let outline = [[2.0, 2.0], [8.0, 2.0], [8.0, 5.0], [5.0, 5.0], [5.0, 8.0], [2.0, 8.0]];
+        let window = PolygonWindow::new(10, 10, &outline);
+        assert_eq!(window.gradient().count(), 6 * 3 + 3 * 3);
+        assert_eq!(window.process().count(), 4 + 3);
+
+        // a square with a square hole is the same as a rectangle in rectangle window
+        let outline = [[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0]];
+        let hole = [[2.0, 2.0], [6.0, 2.0], [6.0, 6.0], [2.0, 6.0]];
+        let window = PolygonWindow::with_hole(10, 10, &outline, &hole);
+        let rectangles = RectangleInRectangleWindow {
+            outer: Rectangle([[0, 0], [10, 10]]),
+            inner: Rectangle([[2, 2], [6, 6]]),
+        };
+        assert_eq!(
+            window.gradient().collect::<Vec<Point>>(),
+            rectangles.gradient().collect::<Vec<Point>>()
+        );
+    }
+
+    #[test]
+    fn test_detect_polygon_window() {
+        let (width, height) = (20, 20);
+        let img = RgbaImage::from_fn(width, height, |x, y| {
+            if (5..15).contains(&x) && (5..15).contains(&y) {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+        // a triangle covering the left half of the square's top edge and all of its left edge
+        let outline = [[0.0, 0.0], [10.0, 0.0], [0.0, 20.0]];
+        let window = PolygonWindow::new(width as usize, height as usize, &outline);
+        let process = window.process().collect::<Vec<Point>>();
+        let mut canny = CannyBuilder::with_window(width as usize, height as usize, window).build();
+
+        let edges = canny.detect_edges(&img);
+
+        assert!(edges.points().count() > 0);
+        assert!(edges.points().all(|p| process.contains(&p)));
+        assert!(edges.is_edge(5, 7) || edges.is_edge(4, 7));
+        assert!(!edges.is_edge(14, 7) && !edges.is_edge(15, 7));
+    }
+
+    #[test]
+    fn test_combined_window() {
+        let a = RectangleWindow::new(Rectangle([[0, 0], [6, 6]]));
+        let b = RectangleWindow::new(Rectangle([[4, 4], [10, 10]]));
+
+        let union = a.union(b).gradient().collect::<Vec<Point>>();
+        assert_eq!(union.len(), 36 + 36 - 4);
+        assert!(union.windows(2).all(|p| (p[0][1], p[0][0]) < (p[1][1], p[1][0])));
+        assert_eq!(
+            a.intersection(b).gradient().collect::<Vec<Point>>(),
+            vec![[4, 4], [5, 4], [4, 5], [5, 5]]
+        );
+        assert_eq!(a.difference(b).gradient().count(), 32);
+        assert!(a.difference(b).contains([3, 5]));
+        assert!(!a.difference(b).contains([5, 5]));
+        assert_eq!(b.difference(b).gradient().count(), 0);
+
+        // a frame made from four strips is the same as a rectangle in rectangle window
+        let frame = RectangleWindow::new(Rectangle([[0, 0], [12, 3]]))
+            .union(RectangleWindow::new(Rectangle([[0, 9], [12, 12]])))
+            .union(RectangleWindow::new(Rectangle([[0, 0], [3, 12]])))
+            .union(RectangleWindow::new(Rectangle([[9, 0], [12, 12]])));
+        let rectangles = RectangleInRectangleWindow {
+            outer: Rectangle([[0, 0], [12, 12]]),
+            inner: Rectangle([[3, 3], [9, 9]]),
+        };
+        assert_eq!(
+            frame.gradient().collect::<Vec<Point>>(),
+            rectangles.gradient().collect::<Vec<Point>>()
+        );
+        assert_eq!(
+            frame.process().collect::<Vec<Point>>(),
+            rectangles.process().collect::<Vec<Point>>()
+        );
+
+        // a frame with a logo cut out of the bottom edge
+        let logo = RectangleWindow::new(Rectangle([[5, 9], [8, 12]]));
+        let cut = frame.difference(logo);
+        assert_eq!(cut.gradient().count(), 108 - 9);
+        assert!(cut.process().all(|[x, y]| y < 8 || x < 4 || x > 8));
+    }
+
     #[bench]
     fn bench_detect(b: &mut Bencher) {
         let img = image::open("test_images/test.jpg").unwrap().to_rgba();
@@ -592,4 +3419,34 @@ mod tests {
             canny.detect(&mut img);
         });
     }
+
+    #[bench]
+    fn bench_blur(b: &mut Bencher) {
+        let img = image::open("test_images/test.jpg").unwrap().to_rgba();
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let window = RectangleWindow {
+            rectangle: Rectangle([[0, 0], [width, height]]),
+        };
+        let layout = Layout::new(&window);
+        let kernel = gaussian_kernel(1.4);
+        let luma: Vec<u8> = img.pixels().map(|p| p[1]).collect();
+        let mut out = vec![0; layout.len];
+        let mut scratch = BlurScratch::new(&kernel, &layout);
+        let border = BorderMode::default();
+
+        b.iter(|| {
+            let mut image = luma.clone();
+            blur(
+                width,
+                height,
+                &layout,
+                &mut image,
+                &layout,
+                &mut out,
+                &mut scratch,
+                &kernel,
+                border,
+            );
+        });
+    }
 }
@@ -0,0 +1,355 @@
+//! Morphological operations on edge maps
+//!
+//! Edges along the border of a card are often broken by glare or fingers. Closing the edges,
+//! dilating then eroding them, bridges gaps smaller than the structuring element without
+//! thickening the edges. Opening, eroding then dilating, removes specks smaller than it.
+use image::{Rgba, RgbaImage};
+
+use super::{is_edge, set_edge, EdgeMap, EdgeMapIterator, Layout, Window};
+use crate::data::Point;
+#[cfg(target_arch = "wasm32")]
+use crate::performance;
+
+/// Shape of the neighbourhood looked at around each Point
+///
+/// The shape is a set of offsets from its origin. The origin of the shapes made by the
+/// constructors is the centre of their bounding box.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StructuringElement {
+    offsets: Vec<[isize; 2]>,
+}
+
+impl StructuringElement {
+    /// Element of the Points in a `width` by `height` box for which `f(x, y)` is true
+    ///
+    /// The origin is at (width / 2, height / 2).
+    ///
+    /// # Panics
+    ///
+    /// If the element has no Points.
+    #[must_use]
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn from_fn<F: Fn(usize, usize) -> bool>(width: usize, height: usize, f: F) -> Self {
+        let origin = [(width / 2) as isize, (height / 2) as isize];
+        let offsets: Vec<_> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| [x, y]))
+            .filter(|&[x, y]| f(x, y))
+            .map(|[x, y]| [x as isize - origin[0], y as isize - origin[1]])
+            .collect();
+        assert!(!offsets.is_empty(), "structuring element must not be empty");
+        Self { offsets }
+    }
+
+    /// Filled rectangle
+    #[must_use]
+    pub fn rectangle(width: usize, height: usize) -> Self {
+        Self::from_fn(width, height, |_, _| true)
+    }
+
+    /// Horizontal line, which bridges gaps in horizontal edges only
+    #[must_use]
+    pub fn horizontal(length: usize) -> Self {
+        Self::rectangle(length, 1)
+    }
+
+    /// Vertical line, which bridges gaps in vertical edges only
+    #[must_use]
+    pub fn vertical(length: usize) -> Self {
+        Self::rectangle(1, length)
+    }
+
+    /// Horizontal and vertical lines through the origin, reaching `radius` from it
+    #[must_use]
+    pub fn cross(radius: usize) -> Self {
+        Self::from_fn(2 * radius + 1, 2 * radius + 1, |x, y| {
+            x == radius || y == radius
+        })
+    }
+
+    /// Points no further than `radius` from the origin
+    #[must_use]
+    pub fn disk(radius: usize) -> Self {
+        Self::from_fn(2 * radius + 1, 2 * radius + 1, |x, y| {
+            let (dx, dy) = (x.abs_diff(radius), y.abs_diff(radius));
+            dx * dx + dy * dy <= radius * radius
+        })
+    }
+
+    /// Offsets of the Points from the origin
+    #[must_use]
+    pub fn offsets(&self) -> &[[isize; 2]] {
+        &self.offsets
+    }
+}
+
+/// Preallocated morphological operations on the edges in a window
+///
+/// Edges are loaded from an `EdgeMap` and transformed in place, so operations can be chained.
+/// Points outside the window are ignored: they never become edges when dilating and never remove
+/// edges when eroding.
+pub struct Morphology {
+    width: usize,
+    height: usize,
+    layout: Layout,
+    edges: Vec<u64>,
+    scratch: Vec<u64>,
+}
+
+impl Morphology {
+    /// Create for images of the given size, working on the Points visited by the window's
+    /// edge detection operator
+    #[must_use]
+    pub fn new<T: Window>(width: usize, height: usize, window: &T) -> Self {
+        let layout = Layout::new(window);
+        let words = layout.len.div_ceil(64);
+        Self {
+            width,
+            height,
+            layout,
+            edges: vec![0; words],
+            scratch: vec![0; words],
+        }
+    }
+
+    /// Replace the edges with the edges of an `EdgeMap` that are in the window
+    pub fn load(&mut self, edges: &EdgeMap) -> &mut Self {
+        for word in &mut self.edges {
+            *word = 0;
+        }
+        for [x, y] in edges.points() {
+            if let Some(i) = self.layout.index(x, y) {
+                set_edge(&mut self.edges, i);
+            }
+        }
+        self
+    }
+
+    /// Mark every Point that the element, placed on it, overlaps an edge with
+    pub fn dilate(&mut self, element: &StructuringElement) -> &mut Self {
+        #[cfg(target_arch = "wasm32")]
+        let _timer = performance::Timer::new("morphology::dilate");
+        self.apply(element, false)
+    }
+
+    /// Keep only the edges that the element, placed on them, fits entirely within
+    pub fn erode(&mut self, element: &StructuringElement) -> &mut Self {
+        #[cfg(target_arch = "wasm32")]
+        let _timer = performance::Timer::new("morphology::erode");
+        self.apply(element, true)
+    }
+
+    /// Erode then dilate, removing edges smaller than the element
+    pub fn open(&mut self, element: &StructuringElement) -> &mut Self {
+        self.erode(element).dilate(element)
+    }
+
+    /// Dilate then erode, bridging gaps between edges smaller than the element
+    pub fn close(&mut self, element: &StructuringElement) -> &mut Self {
+        self.dilate(element).erode(element)
+    }
+
+    /// Width of the image
+    #[must_use]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height of the image
+    #[must_use]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Is the pixel at (x, y) an edge
+    #[must_use]
+    pub fn is_edge(&self, x: usize, y: usize) -> bool {
+        self.layout
+            .index(x, y)
+            .is_some_and(|i| is_edge(&self.edges, i))
+    }
+
+    /// Iterate over the edge Points in row major order
+    #[must_use]
+    pub fn points(&self) -> EdgeMapIterator<'_> {
+        EdgeMapIterator::new(&self.layout, &self.edges)
+    }
+
+    /// Draw the edges onto an image
+    ///
+    /// # Panics
+    ///
+    /// If the image is smaller than the edges.
+    pub fn render(&self, img: &mut RgbaImage, colour: Rgba<u8>) {
+        assert!(img.width() as usize >= self.width && img.height() as usize >= self.height);
+        for [x, y] in self.points() {
+            #[allow(clippy::cast_possible_truncation)]
+            img.put_pixel(x as u32, y as u32, colour);
+        }
+    }
+
+    /// Dilate, or erode, every Point in the window into the scratch buffer, then swap buffers
+    fn apply(&mut self, element: &StructuringElement, erode: bool) -> &mut Self {
+        for word in &mut self.scratch {
+            *word = 0;
+        }
+        let (layout, edges) = (&self.layout, &self.edges);
+        let edge_at = |point: Point, [dx, dy]: [isize; 2]| {
+            let i = layout.index(
+                point[0].checked_add_signed(dx)?,
+                point[1].checked_add_signed(dy)?,
+            )?;
+            Some(is_edge(edges, i))
+        };
+        for (span, start) in &layout.spans {
+            for (i, x) in (*start..).zip(span.x.clone()) {
+                let point = [x, span.y];
+                let edge = if erode {
+                    element
+                        .offsets
+                        .iter()
+                        .all(|&offset| edge_at(point, offset).unwrap_or(true))
+                } else {
+                    // each edge spreads to every offset from it
+                    element
+                        .offsets
+                        .iter()
+                        .any(|&[dx, dy]| edge_at(point, [-dx, -dy]).unwrap_or(false))
+                };
+                if edge {
+                    set_edge(&mut self.scratch, i);
+                }
+            }
+        }
+        std::mem::swap(&mut self.edges, &mut self.scratch);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Morphology, StructuringElement};
+    use crate::data::Rectangle;
+    use crate::edge::{set_edge, CannyBuilder, RectangleWindow};
+    use image::{Rgba, RgbaImage};
+
+    /// Morphology over a whole `width` x `height` image with the given edges
+    fn morphology(width: usize, height: usize, edges: &[[usize; 2]]) -> Morphology {
+        let window = RectangleWindow::new(Rectangle::from_dimensions(width, height));
+        let mut morphology = Morphology::new(width, height, &window);
+        for &[x, y] in edges {
+            let i = morphology.layout.index(x, y).unwrap();
+            set_edge(&mut morphology.edges, i);
+        }
+        morphology
+    }
+
+    #[test]
+    fn test_structuring_element() {
+        assert_eq!(
+            StructuringElement::horizontal(3).offsets(),
+            &[[-1, 0], [0, 0], [1, 0]]
+        );
+        assert_eq!(
+            StructuringElement::vertical(2).offsets(),
+            &[[0, -1], [0, 0]]
+        );
+        assert_eq!(StructuringElement::rectangle(3, 3).offsets().len(), 9);
+        assert_eq!(
+            StructuringElement::cross(1).offsets(),
+            &[[0, -1], [-1, 0], [0, 0], [1, 0], [0, 1]]
+        );
+        assert_eq!(StructuringElement::disk(1), StructuringElement::cross(1));
+        assert_eq!(StructuringElement::disk(2).offsets().len(), 13);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_empty_element() {
+        let _ = StructuringElement::from_fn(3, 3, |_, _| false);
+    }
+
+    #[test]
+    fn test_dilate_erode() {
+        let mut morphology = morphology(10, 10, &[[4, 4]]);
+        morphology.dilate(&StructuringElement::cross(1));
+        assert_eq!(
+            morphology.points().collect::<Vec<_>>(),
+            vec![[4, 3], [3, 4], [4, 4], [5, 4], [4, 5]]
+        );
+        morphology.erode(&StructuringElement::cross(1));
+        assert_eq!(morphology.points().collect::<Vec<_>>(), vec![[4, 4]]);
+        morphology.erode(&StructuringElement::cross(1));
+        assert_eq!(morphology.points().count(), 0);
+
+        // an asymmetric element spreads edges in the direction of its offsets
+        let mut morphology = self::morphology(10, 10, &[[4, 4]]);
+        morphology.dilate(&StructuringElement::from_fn(3, 1, |x, _| x > 0));
+        assert_eq!(
+            morphology.points().collect::<Vec<_>>(),
+            vec![[4, 4], [5, 4]]
+        );
+    }
+
+    #[test]
+    fn test_close_open() {
+        // a horizontal line with a 3 pixel gap, and a speck
+        let line = (3..13).filter(|x| !(6..9).contains(x)).map(|x| [x, 5]);
+        let mut edges: Vec<_> = line.collect();
+        edges.push([8, 12]);
+        let mut morphology = morphology(16, 16, &edges);
+
+        morphology.close(&StructuringElement::horizontal(3));
+        assert!(!morphology.is_edge(7, 5));
+        morphology.close(&StructuringElement::horizontal(5));
+        assert!((3..13).all(|x| morphology.is_edge(x, 5)));
+        // closing does not thicken the line or grow its ends
+        assert!(!morphology.is_edge(2, 5) && !morphology.is_edge(13, 5));
+        assert!((0..16).all(|x| !morphology.is_edge(x, 4) && !morphology.is_edge(x, 6)));
+        assert!(morphology.is_edge(8, 12));
+
+        // the speck is smaller than the element, the line is not
+        morphology.open(&StructuringElement::horizontal(3));
+        assert!(!morphology.is_edge(8, 12));
+        assert!((3..13).all(|x| morphology.is_edge(x, 5)));
+    }
+
+    #[test]
+    fn test_window() {
+        let (width, height) = (60, 40);
+        // white rectangle on a black background
+        let img = RgbaImage::from_fn(width as u32, height as u32, |x, y| {
+            if (10..50).contains(&x) && (10..30).contains(&y) {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        let mut canny = CannyBuilder::new(width, height).build();
+        let edges = canny.detect_edges(&img);
+
+        // only the edges in the window are loaded
+        let window = RectangleWindow::new(Rectangle([[0, 0], [width, 20]]));
+        let mut morphology = Morphology::new(width, height, &window);
+        morphology.load(edges);
+        let points: Vec<_> = morphology.points().collect();
+        assert!(!points.is_empty());
+        assert_eq!(
+            points,
+            edges.points().filter(|&[_, y]| y < 20).collect::<Vec<_>>()
+        );
+
+        // dilation stops at the edge of the window, and erosion ignores what is beyond it
+        morphology.dilate(&StructuringElement::rectangle(5, 5));
+        assert!(morphology.points().all(|[_, y]| y < 20));
+        assert!(morphology.points().any(|[_, y]| y == 19));
+        morphology.erode(&StructuringElement::rectangle(5, 5));
+        assert_eq!(morphology.points().collect::<Vec<_>>(), points);
+
+        let mut out = RgbaImage::new(width as u32, height as u32);
+        morphology.render(&mut out, Rgba([1, 2, 3, 4]));
+        assert_eq!(
+            out.pixels().filter(|&&p| p == Rgba([1, 2, 3, 4])).count(),
+            points.len()
+        );
+    }
+}
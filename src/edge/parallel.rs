@@ -0,0 +1,308 @@
+//! Run the Canny stages on several threads
+//!
+//! The window is split into horizontal bands of rows and each band is processed on its own
+//! thread. Hysteresis follows edges within each band first, then follows the edges that cross
+//! the seams between bands, so the results are identical to running the stages sequentially.
+use std::{ops::Range, thread};
+
+use super::{
+    follow_edges, is_edge, set_edge, BorderMode, GradientKernel, GradientScratch, Layout,
+    Magnitude, Span,
+};
+
+/// Horizontal band of a `Layout` that can be processed independently of the others
+struct Band<'a> {
+    rows: Range<usize>,
+    spans: &'a [(Span, usize)],
+    process: &'a [Span],
+    /// Indices of the Points stored on the rows
+    indices: Range<usize>,
+}
+
+impl Layout {
+    /// Index of the first Point stored on or after row `y`
+    fn row_index(&self, y: usize) -> usize {
+        let row = y.saturating_sub(self.top).min(self.rows.len() - 1);
+        self.spans
+            .get(self.rows[row])
+            .map_or(self.len, |(_, start)| *start)
+    }
+
+    /// Split the rows into at most `n` bands with similar numbers of rows
+    fn bands(&self, n: usize) -> Vec<Band<'_>> {
+        let rows = self.rows.len() - 1;
+        let n = n.clamp(1, rows.max(1));
+        let process = |y| self.process.partition_point(|span| span.y < y);
+        (0..n)
+            .map(|band| {
+                let start = self.top + rows * band / n;
+                let end = self.top + rows * (band + 1) / n;
+                Band {
+                    rows: start..end,
+                    spans: &self.spans[self.rows[start - self.top]..self.rows[end - self.top]],
+                    process: &self.process[process(start)..process(end)],
+                    indices: self.row_index(start)..self.row_index(end),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Split a buffer indexed by layout into the parts for each band
+fn split<'a, O>(mut buffer: &'a mut [O], bands: &[Band<'_>]) -> Vec<&'a mut [O]> {
+    bands
+        .iter()
+        .map(|band| {
+            let (part, rest) = std::mem::take(&mut buffer).split_at_mut(band.indices.len());
+            buffer = rest;
+            part
+        })
+        .collect()
+}
+
+/// Calculate the gradient of each band on its own thread
+#[allow(clippy::similar_names, clippy::too_many_arguments)]
+pub(super) fn gradient<M: Magnitude>(
+    threads: usize,
+    width: usize,
+    height: usize,
+    source: &Layout,
+    image: &[u8],
+    layout: &Layout,
+    hout: &mut [i16],
+    vout: &mut [i16],
+    out: &mut [M],
+    scratch: &mut Vec<GradientScratch>,
+    kernel: GradientKernel,
+    border: BorderMode,
+) {
+    let bands = layout.bands(threads);
+    if scratch.len() < bands.len() {
+        scratch.resize_with(bands.len(), || GradientScratch::new(layout.widest()));
+    }
+    let parts = split(hout, &bands)
+        .into_iter()
+        .zip(split(vout, &bands))
+        .zip(split(out, &bands));
+    thread::scope(|scope| {
+        for ((band, scratch), ((hout, vout), out)) in bands.iter().zip(scratch).zip(parts) {
+            scope.spawn(move || {
+                super::gradient(
+                    width, height, source, image, band.spans, hout, vout, out, scratch, kernel,
+                    border,
+                );
+            });
+        }
+    });
+}
+
+/// Suppress the non-maximum gradients of each band on its own thread
+pub(super) fn non_maximum_suppression<M: Magnitude>(
+    threads: usize,
+    layout: &Layout,
+    g: &[M],
+    gx: &[i16],
+    gy: &[i16],
+    out: &mut [M],
+) {
+    let bands = layout.bands(threads);
+    let parts = split(out, &bands);
+    thread::scope(|scope| {
+        for (band, out) in bands.iter().zip(parts) {
+            scope.spawn(move || {
+                super::non_maximum_suppression(
+                    layout,
+                    band.process,
+                    g,
+                    gx,
+                    gy,
+                    out,
+                    band.indices.start,
+                );
+            });
+        }
+    });
+}
+
+/// Follow the edges of each band on its own thread, then follow the edges across the seams
+pub(super) fn hysteresis<M: Magnitude>(
+    threads: usize,
+    layout: &Layout,
+    input: &[M],
+    out: &mut [u64],
+    low_thresh: f32,
+    high_thresh: f32,
+) {
+    let bands = layout.bands(threads);
+    let parts: Vec<Vec<u64>> = thread::scope(|scope| {
+        let handles: Vec<_> = bands
+            .iter()
+            .map(|band| {
+                scope.spawn(move || {
+                    let mut part = vec![0; band.indices.len().div_ceil(64)];
+                    super::hysteresis(
+                        layout,
+                        band.process,
+                        &band.rows,
+                        input,
+                        &mut part,
+                        band.indices.start,
+                        low_thresh,
+                        high_thresh,
+                    );
+                    part
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("hysteresis thread panicked"))
+            .collect()
+    });
+
+    for word in out.iter_mut() {
+        *word = 0;
+    }
+    for (band, part) in bands.iter().zip(&parts) {
+        for (word, &bits) in part.iter().enumerate() {
+            let mut bits = bits;
+            while bits != 0 {
+                set_edge(
+                    out,
+                    band.indices.start + word * 64 + bits.trailing_zeros() as usize,
+                );
+                bits &= bits - 1;
+            }
+        }
+    }
+
+    // edges found in one band may continue into the next
+    let rows = layout.rows();
+    let mut edges = Vec::new();
+    for band in bands.iter().skip(1) {
+        for y in band.rows.start - 1..=band.rows.start {
+            for (Span { x, .. }, start) in layout.row(y) {
+                for (i, x) in (*start..).zip(x.clone()) {
+                    if is_edge(out, i) {
+                        edges.push([x, y]);
+                        follow_edges(
+                            layout,
+                            &rows,
+                            input,
+                            out,
+                            0,
+                            M::threshold(low_thresh),
+                            &mut edges,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        Arithmetic, AutoThreshold, CannyBuilder, Layout, PolygonWindow, RectangleInRectangleWindow,
+        RectangleWindow, Window,
+    };
+    use crate::data::Rectangle;
+    use image::{self, RgbaImage};
+
+    /// Run every stage sequentially and on threads and compare the buffers
+    fn assert_identical<W: Window>(img: &RgbaImage, window: W, arithmetic: Arithmetic) {
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let mut canny = CannyBuilder::with_window(width, height, window)
+            .auto_threshold(AutoThreshold::Median {
+                low: 0.5,
+                high: 1.0,
+            })
+            .arithmetic(arithmetic)
+            .build();
+        canny.threads = 1;
+        canny.detect_edges(img);
+        let (gx, gy, magnitude) = (
+            canny.edges.gx.clone(),
+            canny.edges.gy.clone(),
+            canny.edges.magnitude.clone(),
+        );
+        let (suppressed, edges) = (canny.suppressed.clone(), canny.edges.edges.clone());
+        assert!(edges.iter().any(|&word| word != 0));
+
+        for &threads in &[2, 3, 7, 64] {
+            canny.threads = threads;
+            canny.detect_edges(img);
+            assert_eq!(canny.edges.gx, gx, "{} threads", threads);
+            assert_eq!(canny.edges.gy, gy, "{} threads", threads);
+            assert_eq!(canny.edges.magnitude, magnitude, "{} threads", threads);
+            assert_eq!(canny.suppressed, suppressed, "{} threads", threads);
+            assert_eq!(canny.edges.edges, edges, "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn test_bands() {
+        let window = RectangleInRectangleWindow::new(
+            Rectangle([[0, 0], [20, 30]]),
+            Rectangle([[5, 5], [15, 25]]),
+        );
+        let layout = Layout::new(&window);
+        let bands = layout.bands(4);
+        assert_eq!(bands.len(), 4);
+        assert_eq!(bands[0].rows, 0..7);
+        assert_eq!(bands[3].rows, 22..30);
+        assert_eq!(bands[0].indices.start, 0);
+        assert_eq!(bands[3].indices.end, layout.len);
+        assert!(bands
+            .windows(2)
+            .all(|pair| pair[0].indices.end == pair[1].indices.start));
+        assert_eq!(bands.iter().map(|band| band.spans.len()).sum::<usize>(), 50);
+        assert_eq!(
+            bands.iter().map(|band| band.process.len()).sum::<usize>(),
+            layout.process.len()
+        );
+
+        // never more bands than rows
+        assert_eq!(layout.bands(100).len(), 30);
+    }
+
+    #[test]
+    fn test_parallel() {
+        let img = image::open("test_images/uno-7.jpg").unwrap().to_rgba();
+        let (width, height) = (img.width() as usize, img.height() as usize);
+
+        assert_identical(
+            &img,
+            RectangleWindow::new(Rectangle([[0, 0], [width, height]])),
+            Arithmetic::Float,
+        );
+        assert_identical(
+            &img,
+            RectangleWindow::new(Rectangle([[0, 0], [width, height]])),
+            Arithmetic::Integer,
+        );
+        let outer = Rectangle([[0, 0], [width, height]]);
+        assert_identical(
+            &img,
+            RectangleInRectangleWindow::new(outer, outer.shrink(40)),
+            Arithmetic::Float,
+        );
+        #[allow(clippy::cast_precision_loss)]
+        let (width_f, height_f) = (width as f32, height as f32);
+        assert_identical(
+            &img,
+            PolygonWindow::new(
+                width,
+                height,
+                &[
+                    [width_f * 0.5, 0.0],
+                    [width_f, height_f * 0.5],
+                    [width_f * 0.5, height_f],
+                    [0.0, height_f * 0.5],
+                ],
+            ),
+            Arithmetic::Float,
+        );
+    }
+}
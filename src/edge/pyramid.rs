@@ -0,0 +1,283 @@
+//! Detect edges in downsampled copies of an image
+//!
+//! Each level of a pyramid is half the width and height of the one before. Fine detail, such as
+//! the printed artwork on a card, is averaged away in the smaller levels while large outlines
+//! survive, and running Canny on a small level is far cheaper than on the full image.
+use image::RgbaImage;
+
+use super::{Canny, EdgeMap, Window};
+use crate::data::Point;
+#[cfg(target_arch = "wasm32")]
+use crate::performance;
+
+/// Preallocated image pyramid
+///
+/// Level 0 is the full resolution image, which is used as it is rather than copied into the
+/// pyramid. Each level after that averages 2x2 blocks of pixels from the level before, dropping
+/// the last row or column when it has an odd number.
+pub struct Pyramid {
+    /// Size of the full resolution image
+    dimensions: (u32, u32),
+    /// Levels from 1 down
+    levels: Vec<RgbaImage>,
+}
+
+impl Pyramid {
+    /// Create a pyramid for images of the given size with `levels` levels below the full image
+    ///
+    /// # Panics
+    ///
+    /// If the smallest level would have no pixels.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(width: usize, height: usize, levels: usize) -> Self {
+        assert!(
+            width >> levels > 0 && height >> levels > 0,
+            "a {}x{} image cannot be halved {} times",
+            width,
+            height,
+            levels
+        );
+        Self {
+            dimensions: (width as u32, height as u32),
+            levels: (1..=levels)
+                .map(|level| RgbaImage::new((width >> level) as u32, (height >> level) as u32))
+                .collect(),
+        }
+    }
+
+    /// Number of levels below the full image
+    #[must_use]
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// The image at a level below the full image
+    ///
+    /// # Panics
+    ///
+    /// If the level is 0 or the pyramid does not have the level.
+    #[must_use]
+    pub fn level(&self, level: usize) -> &RgbaImage {
+        assert!(level > 0, "level 0 is the full resolution image");
+        &self.levels[level - 1]
+    }
+
+    /// Fill every level of the pyramid from a full resolution image
+    ///
+    /// # Panics
+    ///
+    /// If the image is not the size the pyramid was created for.
+    pub fn update(&mut self, image: &RgbaImage) {
+        #[cfg(target_arch = "wasm32")]
+        let _timer = performance::Timer::new("pyramid::update");
+        assert_eq!(
+            image.dimensions(),
+            self.dimensions,
+            "image must be the same size as the pyramid"
+        );
+        let mut above = image;
+        for level in &mut self.levels {
+            downsample(above, level);
+            above = level;
+        }
+    }
+
+    /// Detect edges at a level, with the results mapped back to full resolution
+    ///
+    /// The detector must have been built for the size of the level, see `level`.
+    ///
+    /// # Panics
+    ///
+    /// If the level is 0, the pyramid does not have the level or the detector is not the size of
+    /// the level.
+    pub fn detect_edges<'a, T: Window>(
+        &self,
+        level: usize,
+        canny: &'a mut Canny<T>,
+    ) -> ScaledEdges<'a> {
+        let image = self.level(level);
+        assert!(
+            canny.width == image.width() as usize && canny.height == image.height() as usize,
+            "detector must be the same size as level {}",
+            level
+        );
+        ScaledEdges {
+            edges: canny.detect_edges(image),
+            level,
+        }
+    }
+
+    /// Full resolution pixel at the centre of a pixel at a level
+    ///
+    /// The centre of an even sized block falls between pixels, the one above and to the left is
+    /// used.
+    #[must_use]
+    pub fn to_full_resolution(level: usize, point: Point) -> Point {
+        let half = ((1 << level) - 1) / 2;
+        [(point[0] << level) + half, (point[1] << level) + half]
+    }
+
+    /// Full resolution position of a sub-pixel position at a level
+    ///
+    /// Pixel centres are at whole numbers at every level.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn to_full_resolution_f32(level: usize, point: [f32; 2]) -> [f32; 2] {
+        let scale = (1 << level) as f32;
+        let offset = (scale - 1.0) / 2.0;
+        [point[0] * scale + offset, point[1] * scale + offset]
+    }
+
+    /// Position at a level of the pixel covering a full resolution position
+    #[must_use]
+    pub fn to_level(level: usize, point: Point) -> Point {
+        [point[0] >> level, point[1] >> level]
+    }
+}
+
+/// Edges found at one level of a `Pyramid`
+///
+/// Positions are at full resolution, each edge at a level stands for the block of full resolution
+/// pixels it was averaged from.
+pub struct ScaledEdges<'a> {
+    edges: &'a EdgeMap,
+    level: usize,
+}
+
+impl<'a> ScaledEdges<'a> {
+    /// The pyramid level the edges were found at
+    #[must_use]
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    /// The edges in the coordinates of their level
+    #[must_use]
+    pub fn edges(&self) -> &'a EdgeMap {
+        self.edges
+    }
+
+    /// Is the full resolution pixel at (x, y) covered by an edge
+    #[must_use]
+    pub fn is_edge(&self, x: usize, y: usize) -> bool {
+        let [x, y] = Pyramid::to_level(self.level, [x, y]);
+        self.edges.is_edge(x, y)
+    }
+
+    /// Iterate over the full resolution centres of the edges in row major order
+    pub fn points(&self) -> impl Iterator<Item = Point> + 'a {
+        let level = self.level;
+        self.edges
+            .points()
+            .map(move |point| Pyramid::to_full_resolution(level, point))
+    }
+
+    /// Iterate over the full resolution sub-pixel positions of the edges
+    ///
+    /// Empty unless the detector was built with `CannyBuilder::sub_pixel`.
+    pub fn sub_pixel_points(&self) -> impl Iterator<Item = [f32; 2]> + 'a {
+        let level = self.level;
+        self.edges
+            .sub_pixel_points()
+            .iter()
+            .map(move |&point| Pyramid::to_full_resolution_f32(level, point))
+    }
+}
+
+/// Average each 2x2 block of pixels in `src` into one pixel of `dst`
+#[allow(clippy::cast_possible_truncation)]
+fn downsample(src: &RgbaImage, dst: &mut RgbaImage) {
+    let src_row = src.width() as usize * 4;
+    let dst_row = dst.width() as usize * 4;
+    let src: &[u8] = src;
+    for (y, dst) in dst.chunks_exact_mut(dst_row).enumerate() {
+        let top = &src[2 * y * src_row..(2 * y + 1) * src_row];
+        let bottom = &src[(2 * y + 1) * src_row..(2 * y + 2) * src_row];
+        for (i, dst) in dst.iter_mut().enumerate() {
+            // channel c of pixel x is at 4x + c, and its block starts at 8x + c
+            let left = i + (i & !3);
+            let total = u16::from(top[left])
+                + u16::from(top[left + 4])
+                + u16::from(bottom[left])
+                + u16::from(bottom[left + 4]);
+            *dst = ((total + 2) / 4) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pyramid;
+    use crate::edge::CannyBuilder;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_update() {
+        let img = RgbaImage::from_fn(9, 6, |x, y| Rgba([x as u8 * 10, y as u8 * 10, 7, 255]));
+        let mut pyramid = Pyramid::new(9, 6, 2);
+        pyramid.update(&img);
+        assert_eq!(pyramid.levels(), 2);
+        assert_eq!(pyramid.level(1).dimensions(), (4, 3));
+        assert_eq!(pyramid.level(2).dimensions(), (2, 1));
+
+        // each channel is the rounded average of its 2x2 block
+        assert_eq!(pyramid.level(1).get_pixel(0, 0), &Rgba([5, 5, 7, 255]));
+        assert_eq!(pyramid.level(1).get_pixel(3, 2), &Rgba([65, 45, 7, 255]));
+        assert_eq!(pyramid.level(2).get_pixel(1, 0), &Rgba([55, 15, 7, 255]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_too_many_levels() {
+        let _ = Pyramid::new(9, 6, 3);
+    }
+
+    #[test]
+    fn test_to_full_resolution() {
+        assert_eq!(Pyramid::to_full_resolution(0, [3, 4]), [3, 4]);
+        assert_eq!(Pyramid::to_full_resolution(1, [3, 4]), [6, 8]);
+        assert_eq!(Pyramid::to_full_resolution(2, [3, 4]), [13, 17]);
+        assert_eq!(Pyramid::to_level(2, [15, 19]), [3, 4]);
+        assert_eq!(Pyramid::to_full_resolution_f32(1, [3.0, 4.5]), [6.5, 9.5]);
+        assert_eq!(Pyramid::to_full_resolution_f32(2, [0.0, 0.0]), [1.5, 1.5]);
+    }
+
+    #[test]
+    fn test_detect_edges() {
+        let (width, height) = (80, 60);
+        // white rectangle on a black background
+        let img = RgbaImage::from_fn(width, height, |x, y| {
+            if (20..60).contains(&x) && (16..48).contains(&y) {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        let mut pyramid = Pyramid::new(80, 60, 1);
+        pyramid.update(&img);
+
+        let mut full = CannyBuilder::new(80, 60).build();
+        let full_points = full.detect_edges(&img).points().count();
+        let mut coarse = CannyBuilder::new(40, 30).sub_pixel(true).build();
+        let edges = pyramid.detect_edges(1, &mut coarse);
+        assert_eq!(edges.level(), 1);
+
+        // the outline is found with roughly half as many edges
+        let points = edges.points().collect::<Vec<_>>();
+        assert!(!points.is_empty());
+        assert!(points.len() * 3 < full_points * 2);
+        for &[x, y] in &points {
+            assert!(edges.is_edge(x, y) && edges.is_edge(x + 1, y + 1));
+            let near = |a: usize, b: usize| a.max(b) - a.min(b) <= 2;
+            assert!(
+                near(x, 20) || near(x, 59) || near(y, 16) || near(y, 47),
+                "{} {}",
+                x,
+                y
+            );
+        }
+        assert_eq!(edges.sub_pixel_points().count(), points.len());
+        assert!(edges.edges().is_edge(points[0][0] / 2, points[0][1] / 2));
+    }
+}
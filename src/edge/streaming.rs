@@ -0,0 +1,782 @@
+//! Canny edge detection on images pushed in a row at a time
+//!
+//! Photos of whole sheets of cards are too large to hold every Canny buffer for at once. Here
+//! each stage keeps only the rows its kernel needs, and hysteresis labels the connected
+//! candidate edges as their rows arrive. A row is finished as soon as every candidate on it is
+//! known to be connected to a strong edge, or known not to be because its candidates can no
+//! longer reach one. Memory is proportional to the width of the image, plus any rows held back
+//! by weak edges that are still waiting to reach a strong one.
+use image::{Pixel, Rgba};
+use std::collections::VecDeque;
+
+use super::{
+    blur, gaussian_kernel, gradient, is_edge, non_maximum_suppression, set_edge, BlurScratch,
+    BorderMode, GradientKernel, GradientScratch, Layout, Magnitude, RectangleWindow, Span,
+};
+use crate::data::{Point, Rectangle};
+#[cfg(target_arch = "wasm32")]
+use crate::performance;
+
+/// Build a streaming Canny edge detector
+pub struct StreamingCannyBuilder {
+    width: usize,
+    height: usize,
+    low_threshold: Option<f32>,
+    high_threshold: Option<f32>,
+    blur_sigma: Option<f32>,
+    gradient_kernel: Option<GradientKernel>,
+    border_mode: Option<BorderMode>,
+}
+
+impl StreamingCannyBuilder {
+    /// Create a new builder for images of the given size
+    #[must_use]
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            low_threshold: None,
+            high_threshold: None,
+            blur_sigma: None,
+            gradient_kernel: None,
+            border_mode: None,
+        }
+    }
+
+    /// Set hysteresis low threshold
+    pub fn low_threshold(&mut self, low_threshold: f32) -> &mut Self {
+        self.low_threshold = Some(low_threshold);
+        self
+    }
+
+    /// Set hysteresis high threshold
+    pub fn high_threshold(&mut self, high_threshold: f32) -> &mut Self {
+        self.high_threshold = Some(high_threshold);
+        self
+    }
+
+    /// Set the standard deviation of the Gaussian blur applied before the gradient
+    ///
+    /// See `CannyBuilder::blur_sigma`.
+    pub fn blur_sigma(&mut self, blur_sigma: f32) -> &mut Self {
+        self.blur_sigma = Some(blur_sigma);
+        self
+    }
+
+    /// Set the kernel used to calculate the image gradient
+    ///
+    /// See `CannyBuilder::gradient_kernel`.
+    pub fn gradient_kernel(&mut self, gradient_kernel: GradientKernel) -> &mut Self {
+        self.gradient_kernel = Some(gradient_kernel);
+        self
+    }
+
+    /// Set how the image gradient is calculated at the edge of the image
+    ///
+    /// See `CannyBuilder::border_mode`.
+    pub fn border_mode(&mut self, border_mode: BorderMode) -> &mut Self {
+        self.border_mode = Some(border_mode);
+        self
+    }
+
+    /// Build a streaming canny edge detector
+    ///
+    /// # Panics
+    ///
+    /// If the image is less than 3 pixels wide.
+    #[must_use]
+    pub fn build(&self) -> StreamingCanny {
+        StreamingCanny::new(self)
+    }
+}
+
+/// Canny edge detector that takes an image a row at a time
+///
+/// The edges found are the same as those found by a `Canny` detector with the same settings and
+/// a window covering the whole image. Automatic thresholds are not supported, as they depend on
+/// every row of the image.
+///
+/// Push the rows of an image in order from the top with `push_row`, and take the finished rows of
+/// edges, also in order, with `pop_row`. Once the last row is pushed every row is finished and
+/// the detector is ready for the next image.
+pub struct StreamingCanny {
+    width: usize,
+    height: usize,
+    gradient_kernel: GradientKernel,
+    border_mode: BorderMode,
+    blur_kernel: Vec<u32>,
+
+    /// Rows pushed so far
+    rows: usize,
+    /// Luma of the rows under the blur kernel, unused without a blur
+    luma: Vec<u8>,
+    /// Rows blurred so far
+    blurred_rows: usize,
+    /// Blurred luma of the rows under the gradient kernel
+    blurred: Vec<u8>,
+    /// Contiguous copy of the rows under a kernel
+    strip: Vec<u8>,
+    /// Layout of the whole rows of the strip
+    strip_layout: Layout,
+    gradient_scratch: GradientScratch,
+    /// Blur output for one row
+    blur_out: Vec<u8>,
+    blur_scratch: BlurScratch,
+    /// Layout of the middle row of the blur strip
+    blur_layout: Layout,
+
+    /// Rows of gradient calculated so far
+    gradient_rows: usize,
+    /// Gradient of the last three rows
+    gx: Vec<i16>,
+    gy: Vec<i16>,
+    magnitude: Vec<f32>,
+    /// Layout of the last three rows of gradient
+    layout: Layout,
+    /// Rows of non-maximum suppression done so far
+    suppressed_rows: usize,
+    /// Suppressed magnitudes of the middle of the last three rows
+    suppressed: Vec<f32>,
+
+    hysteresis: Hysteresis,
+    finished: VecDeque<EdgeRow>,
+}
+
+impl StreamingCanny {
+    fn new(builder: &StreamingCannyBuilder) -> Self {
+        let (width, height) = (builder.width, builder.height);
+        assert!(width >= 3, "image must be at least 3 pixels wide");
+        let blur_kernel = gaussian_kernel(builder.blur_sigma.unwrap_or(0.0));
+        let gradient_kernel = builder.gradient_kernel.unwrap_or_default();
+        let strip_rows = blur_kernel.len().max(gradient_kernel.size());
+        let radius = blur_kernel.len() / 2;
+        let blur_layout = Layout {
+            top: radius,
+            rows: vec![0, 1],
+            spans: vec![(
+                Span {
+                    y: radius,
+                    x: 0..width,
+                },
+                0,
+            )],
+            process: vec![],
+            len: width,
+        };
+        Self {
+            width,
+            height,
+            gradient_kernel,
+            border_mode: builder.border_mode.unwrap_or_default(),
+
+            rows: 0,
+            luma: vec![0; blur_kernel.len() * width],
+            blurred_rows: 0,
+            blurred: vec![0; gradient_kernel.size() * width],
+            strip: vec![0; strip_rows * width],
+            strip_layout: Layout::new(&RectangleWindow::new(Rectangle([
+                [0, 0],
+                [width, strip_rows],
+            ]))),
+            gradient_scratch: GradientScratch::new(width),
+            blur_out: vec![0; width],
+            blur_scratch: BlurScratch::new(&blur_kernel, &blur_layout),
+            blur_layout,
+            blur_kernel,
+
+            gradient_rows: 0,
+            gx: vec![0; 3 * width],
+            gy: vec![0; 3 * width],
+            magnitude: vec![0.0; 3 * width],
+            layout: Layout::new(&RectangleWindow::new(Rectangle([[0, 0], [width, 3]]))),
+            suppressed_rows: 0,
+            suppressed: vec![0.0; 3 * width],
+
+            hysteresis: Hysteresis::new(
+                width,
+                f32::threshold(builder.low_threshold.unwrap_or(150.0)),
+                f32::threshold(builder.high_threshold.unwrap_or(300.0)),
+            ),
+            finished: VecDeque::new(),
+        }
+    }
+
+    /// Width of the image
+    #[must_use]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height of the image
+    #[must_use]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Number of rows pushed that are not finished yet
+    #[must_use]
+    pub fn held_rows(&self) -> usize {
+        self.rows - self.hysteresis.finished_rows
+    }
+
+    /// Push the next row of the image, as RGBA pixels
+    ///
+    /// Any rows this finishes are queued for `pop_row`.
+    ///
+    /// # Panics
+    ///
+    /// If the row is not the width of the image.
+    pub fn push_row(&mut self, row: &[u8]) {
+        #[cfg(target_arch = "wasm32")]
+        let _timer = performance::Timer::new("streaming-canny::push-row");
+        assert_eq!(
+            row.len(),
+            self.width * 4,
+            "row must be the width of the image"
+        );
+        let y = self.rows;
+        self.rows += 1;
+        let luma = if self.blur_kernel.is_empty() {
+            let slot = y % self.gradient_kernel.size();
+            &mut self.blurred[slot * self.width..(slot + 1) * self.width]
+        } else {
+            let slot = y % self.blur_kernel.len();
+            &mut self.luma[slot * self.width..(slot + 1) * self.width]
+        };
+        for (luma, pixel) in luma.iter_mut().zip(row.chunks_exact(4)) {
+            *luma = Rgba::from_slice(pixel).to_luma()[0];
+        }
+
+        if self.blur_kernel.is_empty() {
+            self.blurred_rows = self.rows;
+            self.gradient_rows_ready();
+        } else {
+            let ready = self.ready(self.rows, self.blur_kernel.len() / 2);
+            while self.blurred_rows < ready {
+                self.blur_row();
+            }
+        }
+
+        if self.rows == self.height {
+            self.rows = 0;
+            self.blurred_rows = 0;
+            self.gradient_rows = 0;
+            self.suppressed_rows = 0;
+            self.hysteresis.finished_rows = 0;
+        }
+    }
+
+    /// Take the next finished row of edges, if there is one
+    pub fn pop_row(&mut self) -> Option<EdgeRow> {
+        self.finished.pop_front()
+    }
+
+    /// Number of rows a stage can produce once `done` rows of its input are ready, when it needs
+    /// `radius` rows of input below each row
+    fn ready(&self, done: usize, radius: usize) -> usize {
+        if done == self.height {
+            done
+        } else {
+            done.saturating_sub(radius)
+        }
+    }
+
+    /// Blur the next row from the luma of the rows around it
+    fn blur_row(&mut self) {
+        let (width, y) = (self.width, self.blurred_rows);
+        let kernel_rows = self.blur_kernel.len();
+        let radius = kernel_rows / 2;
+        let mut fits = true;
+        for k_y in 0..kernel_rows {
+            let strip = &mut self.strip[k_y * width..(k_y + 1) * width];
+            if let Some(row) = self.border_mode.index(y + k_y, radius, self.height) {
+                let slot = row % kernel_rows;
+                strip.copy_from_slice(&self.luma[slot * width..(slot + 1) * width]);
+            } else {
+                fits = false;
+                strip.fill(0);
+            }
+        }
+        // rows whose kernel doesn't fit are left unblurred when skipping the border
+        if fits || self.border_mode != BorderMode::Skip {
+            blur(
+                width,
+                kernel_rows,
+                &self.strip_layout,
+                &mut self.strip[..kernel_rows * width],
+                &self.blur_layout,
+                &mut self.blur_out,
+                &mut self.blur_scratch,
+                &self.blur_kernel,
+                self.border_mode,
+            );
+        }
+        let slot = y % self.gradient_kernel.size();
+        self.blurred[slot * width..(slot + 1) * width]
+            .copy_from_slice(&self.strip[radius * width..(radius + 1) * width]);
+        self.blurred_rows += 1;
+        self.gradient_rows_ready();
+    }
+
+    /// Calculate the gradient of every row whose kernel rows are blurred
+    fn gradient_rows_ready(&mut self) {
+        let ready = self.ready(self.blurred_rows, self.gradient_kernel.size() / 2);
+        while self.gradient_rows < ready {
+            self.gradient_row();
+        }
+    }
+
+    /// Calculate the gradient of the next row, then suppress any rows that are ready
+    fn gradient_row(&mut self) {
+        let (width, y) = (self.width, self.gradient_rows);
+        let kernel_rows = self.gradient_kernel.size();
+        let radius = kernel_rows / 2;
+        for buffer in [&mut self.gx, &mut self.gy] {
+            buffer.copy_within(width.., 0);
+        }
+        self.magnitude.copy_within(width.., 0);
+
+        // the rows under the kernel are gathered here so the border only matters left and right
+        let mut skip = false;
+        for k_y in 0..kernel_rows {
+            let strip = &mut self.strip[k_y * width..(k_y + 1) * width];
+            if let Some(row) = self.border_mode.index(y + k_y, radius, self.height) {
+                let slot = row % kernel_rows;
+                strip.copy_from_slice(&self.blurred[slot * width..(slot + 1) * width]);
+            } else {
+                strip.fill(0);
+                skip = self.border_mode == BorderMode::Skip;
+            }
+        }
+        if skip {
+            self.gx[2 * width..].fill(0);
+            self.gy[2 * width..].fill(0);
+            self.magnitude[2 * width..].fill(0.0);
+        } else {
+            gradient(
+                width,
+                kernel_rows,
+                &self.strip_layout,
+                &self.strip[..kernel_rows * width],
+                &[(
+                    Span {
+                        y: radius,
+                        x: 0..width,
+                    },
+                    0,
+                )],
+                &mut self.gx[2 * width..],
+                &mut self.gy[2 * width..],
+                &mut self.magnitude[2 * width..],
+                &mut self.gradient_scratch,
+                self.gradient_kernel,
+                self.border_mode,
+            );
+        }
+        self.gradient_rows += 1;
+
+        let ready = self.ready(self.gradient_rows, 1);
+        while self.suppressed_rows < ready {
+            self.suppress_row();
+        }
+    }
+
+    /// Suppress the non-maximum gradients of the middle of the last three rows, then follow its
+    /// edges
+    fn suppress_row(&mut self) {
+        let (width, y) = (self.width, self.suppressed_rows);
+        // the first and last rows are never processed
+        if y == 0 || y + 1 == self.height {
+            self.suppressed[width..2 * width].fill(0.0);
+        } else {
+            non_maximum_suppression(
+                &self.layout,
+                &self.layout.process,
+                &self.magnitude,
+                &self.gx,
+                &self.gy,
+                &mut self.suppressed,
+                0,
+            );
+        }
+        self.suppressed_rows += 1;
+        self.hysteresis.push_row(
+            y,
+            &self.suppressed[width..2 * width],
+            y + 1 == self.height,
+            &mut self.finished,
+        );
+    }
+}
+
+/// Finished row of edges from a `StreamingCanny`
+#[derive(Clone, Debug, PartialEq)]
+pub struct EdgeRow {
+    y: usize,
+    edges: Vec<u64>,
+}
+
+impl EdgeRow {
+    /// Row of the image
+    #[must_use]
+    pub fn y(&self) -> usize {
+        self.y
+    }
+
+    /// Is the pixel at x an edge
+    #[must_use]
+    pub fn is_edge(&self, x: usize) -> bool {
+        self.edges
+            .get(x / 64)
+            .is_some_and(|_| is_edge(&self.edges, x))
+    }
+
+    /// Iterate over the edge Points from left to right
+    pub fn points(&self) -> impl Iterator<Item = Point> + '_ {
+        self.edges
+            .iter()
+            .enumerate()
+            .flat_map(move |(word, &bits)| {
+                let mut bits = bits;
+                std::iter::from_fn(move || {
+                    if bits == 0 {
+                        return None;
+                    }
+                    let x = word * 64 + bits.trailing_zeros() as usize;
+                    bits &= bits - 1;
+                    Some([x, self.y])
+                })
+            })
+    }
+}
+
+/// Hysteresis over rows of suppressed magnitudes as they arrive
+///
+/// Candidate edges, those at least the low threshold, are labelled with their 8-connected
+/// component using union-find. Components that reach a strong edge are edges. Components with
+/// no candidates on the latest row can never reach one, so are not. Rows are held until all of
+/// their candidates are one or the other.
+struct Hysteresis {
+    width: usize,
+    low_threshold: f32,
+    high_threshold: f32,
+    /// Union-find forest of the labels, label 0 is not a candidate
+    parent: Vec<u32>,
+    /// Whether the component of each root label has a strong edge
+    strong: Vec<bool>,
+    /// One more than the last row each root label had candidates on
+    last_row: Vec<usize>,
+    /// Labels of the latest row
+    previous: Vec<u32>,
+    /// Rows not finished yet with their labels
+    pending: VecDeque<(usize, Vec<u32>)>,
+    /// Label buffers to reuse
+    spare: Vec<Vec<u32>>,
+    /// Rows finished so far
+    finished_rows: usize,
+}
+
+impl Hysteresis {
+    fn new(width: usize, low_threshold: f32, high_threshold: f32) -> Self {
+        Self {
+            width,
+            low_threshold,
+            high_threshold,
+            parent: vec![0],
+            strong: vec![false],
+            last_row: vec![0],
+            previous: vec![0; width],
+            pending: VecDeque::new(),
+            spare: vec![],
+            finished_rows: 0,
+        }
+    }
+
+    /// Label the candidates on the next row and finish any rows that can be
+    #[allow(clippy::cast_possible_truncation)]
+    fn push_row(&mut self, y: usize, input: &[f32], last: bool, out: &mut VecDeque<EdgeRow>) {
+        if y == 0 {
+            self.previous.fill(0);
+        }
+        let mut labels = self.spare.pop().unwrap_or_else(|| vec![0; self.width]);
+        for (x, &magnitude) in input.iter().enumerate() {
+            let strong = magnitude >= self.high_threshold && magnitude > 0.0;
+            if !(strong || magnitude >= self.low_threshold && magnitude > 0.0) {
+                labels[x] = 0;
+                continue;
+            }
+            let neighbours = [
+                x.checked_sub(1).map(|x| labels[x]),
+                x.checked_sub(1).map(|x| self.previous[x]),
+                Some(self.previous[x]),
+                self.previous.get(x + 1).copied(),
+            ];
+            let mut label = 0;
+            for neighbour in neighbours.iter().flatten().copied().filter(|&n| n != 0) {
+                label = if label == 0 {
+                    find(&mut self.parent, neighbour)
+                } else {
+                    self.union(label, neighbour)
+                };
+            }
+            if label == 0 {
+                label = self.parent.len() as u32;
+                self.parent.push(label);
+                self.strong.push(false);
+                self.last_row.push(0);
+            }
+            self.strong[label as usize] |= strong;
+            labels[x] = label;
+        }
+        for &label in &labels {
+            if label != 0 {
+                let root = find(&mut self.parent, label);
+                self.last_row[root as usize] = y + 1;
+            }
+        }
+        self.previous.copy_from_slice(&labels);
+        self.pending.push_back((y, labels));
+
+        // label 0 is its own root, and is neither strong nor on the latest row
+        let (parent, strong, last_row) = (&mut self.parent, &self.strong, &self.last_row);
+        while let Some((_, labels)) = self.pending.front() {
+            let finished = last
+                || labels.iter().all(|&label| {
+                    let root = find(parent, label) as usize;
+                    strong[root] || last_row[root] <= y
+                });
+            if !finished {
+                break;
+            }
+            let (y, labels) = self.pending.pop_front().expect("front row exists");
+            let mut edges = vec![0; self.width.div_ceil(64)];
+            for (x, &label) in labels.iter().enumerate() {
+                if strong[find(parent, label) as usize] {
+                    set_edge(&mut edges, x);
+                }
+            }
+            out.push_back(EdgeRow { y, edges });
+            self.spare.push(labels);
+            self.finished_rows += 1;
+        }
+
+        if last {
+            self.parent.truncate(1);
+            self.strong.truncate(1);
+            self.last_row.truncate(1);
+        } else if self.parent.len() > 2 * self.width * (self.pending.len() + 1) {
+            self.compact();
+        }
+    }
+
+    /// Merge the components of two labels, returning the root of the merged component
+    fn union(&mut self, a: u32, b: u32) -> u32 {
+        let (a, b) = (find(&mut self.parent, a), find(&mut self.parent, b));
+        if a != b {
+            self.parent[b as usize] = a;
+            self.strong[a as usize] |= self.strong[b as usize];
+        }
+        a
+    }
+
+    /// Relabel the components still in use from 1 so labels of finished rows can be reused
+    #[allow(clippy::cast_possible_truncation)]
+    fn compact(&mut self) {
+        let mut relabel = vec![0; self.parent.len()];
+        let (mut parent, mut strong, mut last_row) = (vec![0], vec![false], vec![0]);
+        let rows = self.pending.iter_mut().map(|(_, labels)| labels);
+        for labels in rows.chain(std::iter::once(&mut self.previous)) {
+            for label in labels.iter_mut().filter(|label| **label != 0) {
+                let root = find(&mut self.parent, *label) as usize;
+                if relabel[root] == 0 {
+                    relabel[root] = parent.len() as u32;
+                    parent.push(relabel[root]);
+                    strong.push(self.strong[root]);
+                    last_row.push(self.last_row[root]);
+                }
+                *label = relabel[root];
+            }
+        }
+        self.parent = parent;
+        self.strong = strong;
+        self.last_row = last_row;
+    }
+}
+
+/// Root label of the component of a label, halving the path to it
+fn find(parent: &mut [u32], mut label: u32) -> u32 {
+    while parent[label as usize] != label {
+        let grandparent = parent[parent[label as usize] as usize];
+        parent[label as usize] = grandparent;
+        label = grandparent;
+    }
+    label
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StreamingCanny, StreamingCannyBuilder};
+    use crate::data::Rectangle;
+    use crate::edge::{BorderMode, CannyBuilder, GradientKernel, RectangleWindow};
+    use image::{Rgba, RgbaImage};
+
+    /// Stream an image through a detector, checking the rows come out in order
+    fn stream(canny: &mut StreamingCanny, img: &RgbaImage) -> Vec<Vec<bool>> {
+        let mut rows = vec![];
+        for row in img.rows() {
+            let row: Vec<u8> = row.flat_map(|pixel| pixel.0).collect();
+            canny.push_row(&row);
+            while let Some(edges) = canny.pop_row() {
+                assert_eq!(edges.y(), rows.len());
+                assert!(edges
+                    .points()
+                    .all(|[x, y]| edges.is_edge(x) && y == edges.y()));
+                rows.push(
+                    (0..img.width() as usize)
+                        .map(|x| edges.is_edge(x))
+                        .collect(),
+                );
+            }
+        }
+        assert_eq!(canny.held_rows(), 0);
+        rows
+    }
+
+    #[test]
+    fn test_streaming() {
+        let img = image::open("test_images/uno-7.jpg").unwrap().to_rgba();
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let configs: [(f32, GradientKernel, BorderMode, f32, f32); 5] = [
+            (
+                0.0,
+                GradientKernel::Sobel,
+                BorderMode::Replicate,
+                150.0,
+                300.0,
+            ),
+            (
+                1.4,
+                GradientKernel::Sobel,
+                BorderMode::Replicate,
+                50.0,
+                100.0,
+            ),
+            (
+                1.0,
+                GradientKernel::Sobel5,
+                BorderMode::Reflect,
+                600.0,
+                1200.0,
+            ),
+            (0.0, GradientKernel::Scharr, BorderMode::Zero, 300.0, 1000.0),
+            (0.8, GradientKernel::Prewitt, BorderMode::Skip, 40.0, 200.0),
+        ];
+        for &(sigma, kernel, border, low, high) in &configs {
+            let window = RectangleWindow::new(Rectangle::from_dimensions(width, height));
+            let mut canny = CannyBuilder::with_window(width, height, window)
+                .blur_sigma(sigma)
+                .gradient_kernel(kernel)
+                .border_mode(border)
+                .low_threshold(low)
+                .high_threshold(high)
+                .build();
+            let edges = canny.detect_edges(&img);
+            assert!(edges.points().count() > 100);
+
+            let mut builder = StreamingCannyBuilder::new(width, height);
+            builder
+                .blur_sigma(sigma)
+                .gradient_kernel(kernel)
+                .border_mode(border)
+                .low_threshold(low)
+                .high_threshold(high);
+            // the detector can be reused for the next image
+            let mut streaming = builder.build();
+            for _ in 0..2 {
+                let rows = stream(&mut streaming, &img);
+                assert_eq!(rows.len(), height);
+                for (y, row) in rows.iter().enumerate() {
+                    for (x, &edge) in row.iter().enumerate() {
+                        assert_eq!(edge, edges.is_edge(x, y), "{:?} {} {}", kernel, x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_noise() {
+        // speckled noise has many small components, so labels are reused as rows finish
+        let (width, height) = (40, 300);
+        let mut state = 0x2545_f491_u32;
+        let img = RgbaImage::from_fn(width as u32, height as u32, |_, _| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let value = if state % 5 == 0 { 200 } else { 0 };
+            Rgba([value, value, value, 255])
+        });
+        let window = RectangleWindow::new(Rectangle::from_dimensions(width, height));
+        let mut canny = CannyBuilder::with_window(width, height, window)
+            .low_threshold(200.0)
+            .high_threshold(500.0)
+            .build();
+        let edges = canny.detect_edges(&img);
+
+        let mut streaming = StreamingCannyBuilder::new(width, height)
+            .low_threshold(200.0)
+            .high_threshold(500.0)
+            .build();
+        let rows = stream(&mut streaming, &img);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, &edge) in row.iter().enumerate() {
+                assert_eq!(edge, edges.is_edge(x, y), "{} {}", x, y);
+            }
+        }
+        assert!(rows.iter().flatten().filter(|&&edge| edge).count() > 1000);
+        assert!(edges.points().count() * 2 < width * height);
+    }
+
+    #[test]
+    fn test_held_rows() {
+        // a faint vertical step, which becomes strong from a row near the bottom
+        let (width, height) = (24, 40);
+        let img = |strong_from: u32| {
+            RgbaImage::from_fn(width, height, move |x, y| {
+                if x < 16 {
+                    Rgba([0, 0, 0, 255])
+                } else if y < strong_from {
+                    Rgba([40, 40, 40, 255])
+                } else {
+                    Rgba([250, 250, 250, 255])
+                }
+            })
+        };
+        let mut builder = StreamingCannyBuilder::new(width as usize, height as usize);
+        builder.low_threshold(100.0).high_threshold(500.0);
+
+        let mut canny = builder.build();
+        let mut popped = 0;
+        for (y, row) in img(30).rows().enumerate() {
+            let row: Vec<u8> = row.flat_map(|pixel| pixel.0).collect();
+            canny.push_row(&row);
+            while let Some(row) = canny.pop_row() {
+                popped += 1;
+                // the faint step is an edge as it meets the strong one
+                if row.y() < 29 {
+                    assert_eq!(row.is_edge(16), row.y() > 0, "{}", row.y());
+                }
+            }
+            if (4..28).contains(&y) {
+                // the step is held back while it might still reach a strong edge
+                assert_eq!(popped, 1, "{}", y);
+                assert_eq!(canny.held_rows(), y);
+            }
+        }
+        assert_eq!(popped, height);
+
+        // the faint step never reaches a strong edge, so only finishes with the image
+        let rows = stream(&mut canny, &img(height));
+        assert!(rows.iter().flatten().all(|&edge| !edge));
+    }
+}
@@ -1,16 +1,38 @@
 //! Manage packs of cards for playing online
 #![deny(missing_docs, clippy::pedantic)]
 #![feature(test)]
+#![cfg_attr(feature = "simd", feature(portable_simd))]
 
 use image::RgbaImage;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::Clamped;
 
-mod data;
+pub mod contour;
+pub mod data;
 pub mod edge;
+pub mod lines;
 #[cfg(target_arch = "wasm32")]
 mod performance;
+#[cfg(feature = "simd")]
+mod simd;
 pub mod card;
+pub mod warp;
+
+/// Preset sizes of cards
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub enum Format {
+    /// Poker cards, 2.5 x 3.5 inches
+    Poker,
+    /// Bridge cards, 2.25 x 3.5 inches
+    Bridge,
+    /// Tarot cards, 2.75 x 4.75 inches
+    Tarot,
+    /// Mini euro cards, 44 x 68 mm
+    MiniEuro,
+    /// Square cards
+    Square,
+}
 
 /// Preallocated canny edge detector
 #[wasm_bindgen]
@@ -21,16 +43,72 @@ pub struct Detector {
 
 #[wasm_bindgen]
 impl Detector {
-    /// Create a new detector of a given size
+    /// Create a new detector of a given size, with the boundary against the top left of the frame
     pub fn new(width: u32, height: u32) -> Detector {
-        let detector = card::Detector::builder()
+        Self::for_cards(width, height, card::CardFormat::default())
+    }
+
+    /// Create a new detector of a given size for cards of a preset format, on their side if
+    /// `landscape`
+    #[must_use]
+    pub fn with_format(width: u32, height: u32, format: Format, landscape: bool) -> Detector {
+        let format = match format {
+            Format::Poker => card::CardFormat::POKER,
+            Format::Bridge => card::CardFormat::BRIDGE,
+            Format::Tarot => card::CardFormat::TAROT,
+            Format::MiniEuro => card::CardFormat::MINI_EURO,
+            Format::Square => card::CardFormat::SQUARE,
+        };
+        let format = if landscape {
+            format.landscape()
+        } else {
+            format
+        };
+        Self::for_cards(width, height, format)
+    }
+
+    /// Create a new detector of a given size for cards of a custom size
+    #[must_use]
+    pub fn with_card_size(width: u32, height: u32, card_width: f32, card_height: f32) -> Detector {
+        Self::for_cards(
+            width,
+            height,
+            card::CardFormat::new(card_width, card_height),
+        )
+    }
+
+    fn for_cards(width: u32, height: u32, format: card::CardFormat) -> Detector {
+        let detector = Self::build(width, height, format, card::Placement::TopLeft);
+
+        Detector { detector, boundary_match: false }
+    }
+
+    fn build(
+        width: u32,
+        height: u32,
+        format: card::CardFormat,
+        placement: card::Placement,
+    ) -> card::Detector {
+        card::Detector::builder()
             .card_edge_width(3)
             .detection_window_width(20)
             .low_threshold(150.0)
             .high_threshold(200.0)
-            .build(width as usize, height as usize);
+            .format(format)
+            .placement(placement)
+            .build(width as usize, height as usize)
+    }
 
-        Detector { detector, boundary_match: false }
+    /// move the boundary to fractions of the space left beside and below it, 0, 0 against the
+    /// top left of the frame and 0.5, 0.5 in the middle
+    ///
+    /// # Panics
+    ///
+    /// If either fraction is outside 0 to 1.
+    pub fn place(&mut self, x: f32, y: f32) {
+        let placement = card::Placement::Anchor { x, y };
+        self.detector = Self::build(self.width(), self.height(), self.detector.format(), placement);
+        self.boundary_match = false;
     }
 
     /// has a box been seen?
@@ -38,6 +116,55 @@ impl Detector {
         self.boundary_match
     }
 
+    /// hysteresis low threshold used for the last frame
+    #[must_use]
+    pub fn low_threshold(&self) -> f32 {
+        self.detector.thresholds().0
+    }
+
+    /// hysteresis high threshold used for the last frame
+    #[must_use]
+    pub fn high_threshold(&self) -> f32 {
+        self.detector.thresholds().1
+    }
+
+    /// corners of the card seen in the last frame as x, y pairs clockwise from the top left,
+    /// empty if no card was seen
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn corners(&self) -> Vec<u32> {
+        self.detector
+            .corners()
+            .iter()
+            .flatten()
+            .flatten()
+            .map(|&coordinate| coordinate as u32)
+            .collect()
+    }
+
+    /// clockwise rotation in radians of the card seen in the last frame, if one was seen
+    #[must_use]
+    pub fn rotation(&self) -> Option<f32> {
+        self.detector.rotation()
+    }
+
+    /// upright image of the card seen in the last frame, `width` pixels wide, cut out of a copy
+    /// of that frame that has not been drawn on, empty if no card was seen
+    ///
+    /// # Panics
+    ///
+    /// If the frame is not the size of the detector.
+    pub fn extract(&self, input: Clamped<Vec<u8>>, width: u32) -> Clamped<Vec<u8>> {
+        let input = RgbaImage::from_raw(self.width(), self.height(), input.0).expect("Could not load image");
+
+        Clamped(
+            self.detector
+                .extract(&input, width)
+                .map(RgbaImage::into_raw)
+                .unwrap_or_default(),
+        )
+    }
+
     fn width(&self) -> u32 {
         self.detector.width() as u32
     }
@@ -0,0 +1,544 @@
+//! Find straight lines in Canny edge maps with the Hough transform
+//!
+//! Lines are described in normal form, `x * cos(angle) + y * sin(angle) = offset`, where `angle`
+//! is in the range 0 to π and `offset` is the signed distance of the line from the origin.
+use std::f32;
+
+use crate::data::Point;
+use crate::edge::{EdgeMap, Window};
+#[cfg(target_arch = "wasm32")]
+use crate::performance;
+
+/// A straight line segment found by the Hough transform
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Segment {
+    /// First end of the segment
+    pub start: [f32; 2],
+    /// Second end of the segment
+    pub end: [f32; 2],
+    /// Angle of the line normal in radians
+    pub angle: f32,
+    /// Distance of the line from the origin along its normal
+    pub offset: f32,
+    /// Number of edge pixels that voted for the line
+    pub votes: u32,
+}
+
+impl Segment {
+    /// Describe the line through two points in normal form
+    fn between(start: [f32; 2], end: [f32; 2], votes: u32) -> Segment {
+        // the normal is perpendicular to the direction of the segment
+        let mut angle = (start[0] - end[0]).atan2(end[1] - start[1]);
+        if angle < 0.0 {
+            angle += f32::consts::PI;
+        }
+        if angle >= f32::consts::PI {
+            angle -= f32::consts::PI;
+        }
+        Segment {
+            start,
+            end,
+            angle,
+            offset: start[0] * angle.cos() + start[1] * angle.sin(),
+            votes,
+        }
+    }
+
+    /// Length of the segment in pixels
+    #[must_use]
+    pub fn length(&self) -> f32 {
+        (self.end[0] - self.start[0]).hypot(self.end[1] - self.start[1])
+    }
+}
+
+/// Build a Hough transform
+pub struct HoughBuilder {
+    width: usize,
+    height: usize,
+    angle_resolution: Option<f32>,
+    offset_resolution: Option<f32>,
+    threshold: Option<u32>,
+}
+
+impl HoughBuilder {
+    /// Create a new builder for images of the given size
+    #[must_use]
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            angle_resolution: None,
+            offset_resolution: None,
+            threshold: None,
+        }
+    }
+
+    /// Set the size of each angle bin in radians
+    ///
+    /// Defaults to one degree.
+    pub fn angle_resolution(&mut self, angle_resolution: f32) -> &mut Self {
+        self.angle_resolution = Some(angle_resolution);
+        self
+    }
+
+    /// Set the size of each offset bin in pixels
+    ///
+    /// Defaults to one pixel.
+    pub fn offset_resolution(&mut self, offset_resolution: f32) -> &mut Self {
+        self.offset_resolution = Some(offset_resolution);
+        self
+    }
+
+    /// Set the minimum number of votes needed for a line to be reported
+    ///
+    /// Defaults to 50.
+    pub fn threshold(&mut self, threshold: u32) -> &mut Self {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    /// Build the Hough transform
+    ///
+    /// # Panics
+    ///
+    /// If either resolution is not a positive number.
+    #[must_use]
+    pub fn build(&self) -> Hough {
+        Hough::new(self)
+    }
+}
+
+/// Preallocated Hough transform
+pub struct Hough {
+    width: usize,
+    height: usize,
+    offset_resolution: f32,
+    threshold: u32,
+    cos: Vec<f32>,
+    sin: Vec<f32>,
+    offsets: usize,
+    accumulator: Vec<u32>,
+    remaining: Vec<u64>,
+    voted: Vec<u64>,
+    points: Vec<Point>,
+}
+
+impl Hough {
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn new(builder: &HoughBuilder) -> Self {
+        let (width, height) = (builder.width, builder.height);
+        let angle_resolution = builder.angle_resolution.unwrap_or(f32::consts::PI / 180.0);
+        let offset_resolution = builder.offset_resolution.unwrap_or(1.0);
+        assert!(
+            angle_resolution.is_finite() && angle_resolution > 0.0,
+            "angle resolution must be positive, not {}",
+            angle_resolution
+        );
+        assert!(
+            offset_resolution.is_finite() && offset_resolution > 0.0,
+            "offset resolution must be positive, not {}",
+            offset_resolution
+        );
+        let angles = (f32::consts::PI / angle_resolution).round() as usize;
+        let max_offset = (width as f32).hypot(height as f32);
+        let offsets = 2 * (max_offset / offset_resolution).ceil() as usize + 1;
+
+        Self {
+            width,
+            height,
+            offset_resolution,
+            threshold: builder.threshold.unwrap_or(50),
+            cos: (0..angles)
+                .map(|n| (n as f32 * angle_resolution).cos())
+                .collect(),
+            sin: (0..angles)
+                .map(|n| (n as f32 * angle_resolution).sin())
+                .collect(),
+            offsets,
+            accumulator: vec![0; angles * offsets],
+            remaining: vec![0; (width * height).div_ceil(64)],
+            voted: vec![0; (width * height).div_ceil(64)],
+            points: Vec::new(),
+        }
+    }
+
+    /// Find lines using the standard Hough transform
+    ///
+    /// Every edge pixel within the window votes for every line passing through it. Lines with
+    /// at least `threshold` votes that are the strongest among their neighbouring angles and
+    /// offsets are returned, strongest first. Each line is reported as the segment between the
+    /// outermost edge pixels lying on it.
+    pub fn lines<T: Window>(&mut self, edges: &EdgeMap, window: &T) -> Vec<Segment> {
+        #[cfg(target_arch = "wasm32")]
+        let _timer = performance::Timer::new("hough::lines");
+        self.collect_points(edges, window);
+        for cell in &mut self.accumulator {
+            *cell = 0;
+        }
+        for i in 0..self.points.len() {
+            self.vote(self.points[i], 1);
+        }
+
+        let angles = self.cos.len();
+        let mut peaks = vec![];
+        for n in 0..angles {
+            for r in 0..self.offsets {
+                let votes = self.accumulator[n * self.offsets + r];
+                if votes < self.threshold {
+                    continue;
+                }
+                // angles wrap around, with the offset changing sign
+                let neighbours = [(n + angles - 1) % angles, n, (n + 1) % angles];
+                let is_peak = neighbours.iter().all(|&nn| {
+                    let wrapped = (n == 0 && nn == angles - 1) || (n == angles - 1 && nn == 0);
+                    (r.saturating_sub(1)..=(r + 1).min(self.offsets - 1)).all(|rr| {
+                        let rr = if wrapped { self.offsets - 1 - rr } else { rr };
+                        let other = self.accumulator[nn * self.offsets + rr];
+                        // break ties in favour of the first cell
+                        other < votes || (other == votes && (nn, rr) >= (n, r))
+                    })
+                });
+                if is_peak {
+                    peaks.push((votes, n, r));
+                }
+            }
+        }
+        peaks.sort_by_key(|&(votes, _, _)| std::cmp::Reverse(votes));
+
+        peaks
+            .into_iter()
+            .map(|(votes, n, r)| self.extent(n, r, votes))
+            .collect()
+    }
+
+    /// Find line segments using the progressive probabilistic Hough transform
+    ///
+    /// Edge pixels are visited in a random order. As soon as a line gathers `threshold` votes the
+    /// segment through the current pixel is traced, allowing gaps of up to `max_gap` pixels, and
+    /// its pixels removed from further consideration. Segments shorter than `min_length` are
+    /// discarded.
+    pub fn segments<T: Window>(
+        &mut self,
+        edges: &EdgeMap,
+        window: &T,
+        min_length: f32,
+        max_gap: usize,
+    ) -> Vec<Segment> {
+        #[cfg(target_arch = "wasm32")]
+        let _timer = performance::Timer::new("hough::segments");
+        self.collect_points(edges, window);
+        for cell in &mut self.accumulator {
+            *cell = 0;
+        }
+        for word in &mut self.voted {
+            *word = 0;
+        }
+
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+        let mut segments = vec![];
+        for count in (1..=self.points.len()).rev() {
+            #[allow(clippy::cast_possible_truncation)]
+            let pick = (rng.next() % count as u64) as usize;
+            self.points.swap(pick, count - 1);
+            let point = self.points[count - 1];
+            let i = self.index(point);
+            if !get_bit(&self.remaining, i) {
+                continue;
+            }
+
+            set_bit(&mut self.voted, i, true);
+            let (votes, n) = self.vote(point, 1);
+            if votes < self.threshold {
+                continue;
+            }
+
+            // walk along the line in both directions to find the ends
+            let direction = [-self.sin[n], self.cos[n]];
+            let ends = [
+                self.walk(point, direction, max_gap),
+                self.walk(point, [-direction[0], -direction[1]], max_gap),
+            ];
+            let good_line = point_distance(ends[0], ends[1]) >= min_length;
+
+            // remove the segment's pixels so they do not contribute to other lines
+            let votes = self.clear_segment(ends[0], ends[1], good_line);
+
+            if good_line {
+                #[allow(clippy::cast_precision_loss)]
+                let (start, end) = (
+                    [ends[0][0] as f32, ends[0][1] as f32],
+                    [ends[1][0] as f32, ends[1][1] as f32],
+                );
+                segments.push(Segment::between(start, end, votes));
+            }
+        }
+
+        segments
+    }
+
+    fn index(&self, point: Point) -> usize {
+        point[1] * self.width + point[0]
+    }
+
+    /// Gather the edge pixels within the window
+    fn collect_points<T: Window>(&mut self, edges: &EdgeMap, window: &T) {
+        assert!(
+            edges.width() == self.width && edges.height() == self.height,
+            "edge map must be the same size as the Hough transform"
+        );
+        self.points.clear();
+        for word in &mut self.remaining {
+            *word = 0;
+        }
+        for [x, y] in window.process() {
+            if edges.is_edge(x, y) {
+                self.points.push([x, y]);
+                let i = self.index([x, y]);
+                set_bit(&mut self.remaining, i, true);
+            }
+        }
+    }
+
+    /// Add (or remove) a pixel's votes, returning the strongest line through it
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_possible_wrap,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn vote(&mut self, point: Point, weight: i32) -> (u32, usize) {
+        let (x, y) = (point[0] as f32, point[1] as f32);
+        let centre = (self.offsets / 2) as isize;
+        let mut best = (0, 0);
+        for n in 0..self.cos.len() {
+            let r = ((x * self.cos[n] + y * self.sin[n]) / self.offset_resolution).round() as isize
+                + centre;
+            let cell = &mut self.accumulator[n * self.offsets + r as usize];
+            *cell = (*cell as i32 + weight) as u32;
+            if *cell > best.0 {
+                best = (*cell, n);
+            }
+        }
+        best
+    }
+
+    /// Follow a line from a point until the gap between edge pixels is too large
+    ///
+    /// Returns the last edge pixel found.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn walk(&self, point: Point, direction: [f32; 2], max_gap: usize) -> Point {
+        // step one pixel at a time along the major axis
+        let scale = direction[0].abs().max(direction[1].abs());
+        let step = [direction[0] / scale, direction[1] / scale];
+        let (mut x, mut y) = (point[0] as f32, point[1] as f32);
+        let mut end = point;
+        let mut gap = 0;
+        loop {
+            x += step[0];
+            y += step[1];
+            let (px, py) = (x.round(), y.round());
+            if px < 0.0 || py < 0.0 || px >= self.width as f32 || py >= self.height as f32 {
+                break;
+            }
+            let next = [px as usize, py as usize];
+            if get_bit(&self.remaining, self.index(next)) {
+                gap = 0;
+                end = next;
+            } else {
+                gap += 1;
+                if gap > max_gap {
+                    break;
+                }
+            }
+        }
+        end
+    }
+
+    /// Remove the pixels between two ends of a segment, returning how many there were
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_possible_wrap,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn clear_segment(&mut self, start: Point, end: Point, unvote: bool) -> u32 {
+        let (dx, dy) = (
+            end[0] as isize - start[0] as isize,
+            end[1] as isize - start[1] as isize,
+        );
+        let steps = dx.abs().max(dy.abs());
+        let mut count = 0;
+        for k in 0..=steps {
+            let t = if steps == 0 {
+                0.0
+            } else {
+                k as f32 / steps as f32
+            };
+            let point = [
+                (start[0] as f32 + t * dx as f32).round() as usize,
+                (start[1] as f32 + t * dy as f32).round() as usize,
+            ];
+            let i = self.index(point);
+            if get_bit(&self.remaining, i) {
+                count += 1;
+                if unvote && get_bit(&self.voted, i) {
+                    self.vote(point, -1);
+                }
+                set_bit(&mut self.remaining, i, false);
+            }
+        }
+        count
+    }
+
+    /// The segment between the outermost edge pixels on a line
+    #[allow(clippy::cast_precision_loss)]
+    fn extent(&self, n: usize, r: usize, votes: u32) -> Segment {
+        let offset = (r as f32 - (self.offsets / 2) as f32) * self.offset_resolution;
+        let (cos, sin) = (self.cos[n], self.sin[n]);
+        let mut range = (f32::MAX, f32::MIN);
+        for &[x, y] in &self.points {
+            let (x, y) = (x as f32, y as f32);
+            if (x * cos + y * sin - offset).abs() <= self.offset_resolution {
+                let along = -x * sin + y * cos;
+                range = (range.0.min(along), range.1.max(along));
+            }
+        }
+        let at = |along: f32| [offset * cos - along * sin, offset * sin + along * cos];
+        Segment {
+            start: at(range.0),
+            end: at(range.1),
+            angle: self.sin[n].atan2(self.cos[n]),
+            offset,
+            votes,
+        }
+    }
+}
+
+fn get_bit(bits: &[u64], i: usize) -> bool {
+    bits[i / 64] & (1 << (i % 64)) != 0
+}
+
+fn set_bit(bits: &mut [u64], i: usize, value: bool) {
+    if value {
+        bits[i / 64] |= 1 << (i % 64);
+    } else {
+        bits[i / 64] &= !(1 << (i % 64));
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn point_distance(a: Point, b: Point) -> f32 {
+    (a[0] as f32 - b[0] as f32).hypot(a[1] as f32 - b[1] as f32)
+}
+
+/// Small deterministic random number generator so results are repeatable
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+    use std::f32;
+
+    use super::HoughBuilder;
+    use crate::data::Rectangle;
+    use crate::edge::{CannyBuilder, RectangleWindow};
+
+    /// White square on a black background with a one pixel grey border to avoid ties
+    fn square() -> RgbaImage {
+        RgbaImage::from_fn(100, 80, |x, y| {
+            if (21..79).contains(&x) && (16..64).contains(&y) {
+                Rgba([255, 255, 255, 255])
+            } else if (20..80).contains(&x) && (15..65).contains(&y) {
+                Rgba([128, 128, 128, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn test_lines() {
+        let img = square();
+        let mut canny = CannyBuilder::new(100, 80).build();
+        let edges = canny.detect_edges(&img);
+        let window = RectangleWindow::new(Rectangle([[0, 0], [100, 80]]));
+
+        let lines = HoughBuilder::new(100, 80)
+            .threshold(30)
+            .build()
+            .lines(edges, &window);
+
+        assert_eq!(lines.len(), 4, "{:?}", lines);
+        let horizontal = lines
+            .iter()
+            .filter(|l| (l.angle - f32::consts::FRAC_PI_2).abs() < 0.05)
+            .count();
+        let vertical = lines.iter().filter(|l| l.angle < 0.05).count();
+        assert_eq!((horizontal, vertical), (2, 2));
+        for line in &lines {
+            assert!(line.votes >= 30);
+            assert!(line.length() > 40.0 && line.length() < 65.0, "{:?}", line);
+        }
+        let left = lines
+            .iter()
+            .find(|l| l.angle < 0.05 && l.offset < 50.0)
+            .unwrap();
+        assert!((left.offset - 19.5).abs() <= 1.0, "{:?}", left);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zero_angle_resolution() {
+        let _ = HoughBuilder::new(100, 80).angle_resolution(0.0).build();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_nan_offset_resolution() {
+        let _ = HoughBuilder::new(100, 80)
+            .offset_resolution(f32::NAN)
+            .build();
+    }
+
+    #[test]
+    fn test_segments() {
+        let img = square();
+        let mut canny = CannyBuilder::new(100, 80).build();
+        let edges = canny.detect_edges(&img);
+        // only look at the top of the image
+        let window = RectangleWindow::new(Rectangle([[0, 0], [100, 30]]));
+
+        let segments = HoughBuilder::new(100, 80)
+            .threshold(10)
+            .build()
+            .segments(edges, &window, 30.0, 2);
+
+        assert_eq!(segments.len(), 1, "{:?}", segments);
+        let top = segments[0];
+        assert!(
+            (top.angle - f32::consts::FRAC_PI_2).abs() < 0.05,
+            "{:?}",
+            top
+        );
+        assert!((top.offset - 14.5).abs() <= 1.0, "{:?}", top);
+        assert!(top.length() > 50.0, "{:?}", top);
+        assert!(top.start[1] < 30.0 && top.end[1] < 30.0);
+    }
+}
@@ -0,0 +1,155 @@
+//! SIMD versions of the hottest edge detection loops
+//!
+//! These are written with portable SIMD so they can be tested natively against the scalar
+//! versions, but they are only used when building for wasm32 with the `simd128` target feature,
+//! where each vector operation is a single WebAssembly SIMD instruction.
+#![cfg_attr(
+    not(all(target_arch = "wasm32", target_feature = "simd128")),
+    allow(dead_code)
+)]
+use std::simd::{cmp::SimdOrd, num::SimdInt, num::SimdUint, Simd};
+
+/// Number of Points handled by each vector operation
+const LANES: usize = 8;
+
+/// Add a kernel tap to the running totals of a row of Points
+pub(crate) fn accumulate_taps(acc: &mut [i32], pixels: &[u8], weight: i32) {
+    let mut acc_chunks = acc.chunks_exact_mut(LANES);
+    let mut pixel_chunks = pixels.chunks_exact(LANES);
+    let weights = Simd::splat(weight);
+    for (acc, pixels) in (&mut acc_chunks).zip(&mut pixel_chunks) {
+        let pixels: Simd<i32, LANES> = Simd::<u8, LANES>::from_slice(pixels).cast();
+        (Simd::from_slice(acc) + pixels * weights).copy_to_slice(acc);
+    }
+    for (acc, &p) in acc_chunks
+        .into_remainder()
+        .iter_mut()
+        .zip(pixel_chunks.remainder())
+    {
+        *acc += i32::from(p) * weight;
+    }
+}
+
+/// Store the gradients of a row of Points from their running totals
+///
+/// The totals are clamped to `i16` and the squared magnitude is calculated in `f32` exactly as
+/// the scalar version does, so the results are identical.
+pub(crate) fn store_gradients(
+    hacc: &[i32],
+    vacc: &[i32],
+    hout: &mut [i16],
+    vout: &mut [i16],
+    out: &mut [f32],
+) {
+    let (min, max) = (
+        Simd::splat(i32::from(i16::MIN)),
+        Simd::splat(i32::from(i16::MAX)),
+    );
+    let n = hacc.len() - hacc.len() % LANES;
+    for i in (0..n).step_by(LANES) {
+        let h: Simd<i16, LANES> = Simd::from_slice(&hacc[i..]).simd_clamp(min, max).cast();
+        let v: Simd<i16, LANES> = Simd::from_slice(&vacc[i..]).simd_clamp(min, max).cast();
+        h.copy_to_slice(&mut hout[i..i + LANES]);
+        v.copy_to_slice(&mut vout[i..i + LANES]);
+        let (h, v): (Simd<f32, LANES>, Simd<f32, LANES>) = (h.cast(), v.cast());
+        (h * h + v * v).copy_to_slice(&mut out[i..i + LANES]);
+    }
+    for i in n..hacc.len() {
+        let h = hacc[i].clamp(min[0], max[0]);
+        let v = vacc[i].clamp(min[0], max[0]);
+        #[allow(clippy::cast_possible_truncation)]
+        let (h, v) = (h as i16, v as i16);
+        hout[i] = h;
+        vout[i] = v;
+        out[i] = f32::from(h) * f32::from(h) + f32::from(v) * f32::from(v);
+    }
+}
+
+/// Store the gradients of a row of Points from their running totals, with integer magnitudes
+pub(crate) fn store_integer_gradients(
+    hacc: &[i32],
+    vacc: &[i32],
+    hout: &mut [i16],
+    vout: &mut [i16],
+    out: &mut [u32],
+) {
+    let (min, max) = (
+        Simd::splat(i32::from(i16::MIN)),
+        Simd::splat(i32::from(i16::MAX)),
+    );
+    let n = hacc.len() - hacc.len() % LANES;
+    for i in (0..n).step_by(LANES) {
+        let h = Simd::from_slice(&hacc[i..]).simd_clamp(min, max);
+        let v = Simd::from_slice(&vacc[i..]).simd_clamp(min, max);
+        let (h16, v16): (Simd<i16, LANES>, Simd<i16, LANES>) = (h.cast(), v.cast());
+        h16.copy_to_slice(&mut hout[i..i + LANES]);
+        v16.copy_to_slice(&mut vout[i..i + LANES]);
+        // each square fits in an i32 but their sum only fits in a u32
+        let (h, v): (Simd<u32, LANES>, Simd<u32, LANES>) = ((h * h).cast(), (v * v).cast());
+        (h + v).copy_to_slice(&mut out[i..i + LANES]);
+    }
+    for i in n..hacc.len() {
+        let h = hacc[i].clamp(min[0], max[0]);
+        let v = vacc[i].clamp(min[0], max[0]);
+        #[allow(clippy::cast_possible_truncation)]
+        let (h, v) = (h as i16, v as i16);
+        hout[i] = h;
+        vout[i] = v;
+        let (h, v) = (u32::from(h.unsigned_abs()), u32::from(v.unsigned_abs()));
+        out[i] = h * h + v * v;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::edge;
+
+    /// Deterministic pseudo random pixels covering the whole range
+    fn pixels(n: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_u32;
+        (0..n)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state.to_le_bytes()[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_accumulate_taps() {
+        // lengths either side of a whole number of vectors
+        for &n in &[0, 1, 7, 8, 9, 64, 67] {
+            let pixels = pixels(n);
+            for &weight in &[-12, -1, 1, 3, 10] {
+                let mut scalar = (0..n as i32).collect::<Vec<i32>>();
+                let mut simd = scalar.clone();
+                edge::accumulate_taps(&mut scalar, &pixels, weight);
+                super::accumulate_taps(&mut simd, &pixels, weight);
+                assert_eq!(scalar, simd, "{} {}", n, weight);
+            }
+        }
+    }
+
+    #[test]
+    fn test_store_gradients() {
+        let n = 37;
+        // includes totals beyond the range of i16
+        let hacc = (0..n).map(|i| (i - 18) * 2001).collect::<Vec<i32>>();
+        let vacc = (0..n).map(|i| (7 - i) * 1234).collect::<Vec<i32>>();
+        let mut scalar = (vec![0; 37], vec![0; 37], vec![0.0; 37]);
+        let mut simd = scalar.clone();
+        edge::store_gradients(&hacc, &vacc, &mut scalar.0, &mut scalar.1, &mut scalar.2);
+        super::store_gradients(&hacc, &vacc, &mut simd.0, &mut simd.1, &mut simd.2);
+        assert_eq!(scalar, simd);
+        assert_eq!(simd.0[0], i16::MIN);
+        assert_eq!(simd.0[36], i16::MAX);
+
+        let mut scalar = (vec![0; 37], vec![0; 37], vec![0_u32; 37]);
+        let mut simd = scalar.clone();
+        edge::store_gradients(&hacc, &vacc, &mut scalar.0, &mut scalar.1, &mut scalar.2);
+        super::store_integer_gradients(&hacc, &vacc, &mut simd.0, &mut simd.1, &mut simd.2);
+        assert_eq!(scalar, simd);
+    }
+}
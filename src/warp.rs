@@ -0,0 +1,317 @@
+//! Correct the perspective of a quadrilateral in an image
+//!
+//! A homography maps each pixel of an upright output rectangle to a position in the input image,
+//! and the input is resampled at that position. The input positions fall between pixels so the
+//! colour is interpolated from the pixels around it.
+use image::{Rgba, RgbaImage};
+
+#[cfg(target_arch = "wasm32")]
+use crate::performance;
+
+/// How colours are read from between pixels
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum Interpolation {
+    /// Weighted average of the four nearest pixels
+    #[default]
+    Bilinear,
+    /// Catmull-Rom spline through the sixteen nearest pixels, sharper than `Bilinear`
+    Bicubic,
+}
+
+/// Projective transform of the plane
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Homography([f32; 9]);
+
+impl Homography {
+    /// The homography that maps each of `from` to the matching point of `to`
+    ///
+    /// Returns `None` if three of either set of points are on one line.
+    #[must_use]
+    pub fn from_points(from: [[f32; 2]; 4], to: [[f32; 2]; 4]) -> Option<Self> {
+        if has_collinear(from) || has_collinear(to) {
+            return None;
+        }
+
+        // each pair of points gives two rows of the linear system for the first eight entries
+        // of the matrix, the last is fixed at one
+        let mut system = [[0.0_f64; 9]; 8];
+        for (i, (&[x, y], &[u, v])) in from.iter().zip(&to).enumerate() {
+            let (x, y, u, v) = (f64::from(x), f64::from(y), f64::from(u), f64::from(v));
+            system[2 * i] = [x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y, u];
+            system[2 * i + 1] = [0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y, v];
+        }
+
+        // Gaussian elimination with partial pivoting
+        for column in 0..8 {
+            let pivot = (column..8)
+                .max_by(|&a, &b| system[a][column].abs().total_cmp(&system[b][column].abs()))
+                .unwrap_or(column);
+            if system[pivot][column].abs() < 1e-9 {
+                return None;
+            }
+            system.swap(column, pivot);
+            let pivot = system[column];
+            for (i, row) in system.iter_mut().enumerate() {
+                if i != column {
+                    let factor = row[column] / pivot[column];
+                    for (value, pivot) in row[column..].iter_mut().zip(&pivot[column..]) {
+                        *value -= factor * pivot;
+                    }
+                }
+            }
+        }
+
+        // only the diagonal is left
+        let mut matrix = [1.0; 9];
+        for (i, (entry, row)) in matrix.iter_mut().zip(&system).enumerate() {
+            #[allow(clippy::cast_possible_truncation)]
+            let value = (row[8] / row[i]) as f32;
+            *entry = value;
+        }
+        Some(Self(matrix))
+    }
+
+    /// Map a point through the homography
+    #[must_use]
+    pub fn apply(&self, [x, y]: [f32; 2]) -> [f32; 2] {
+        let m = &self.0;
+        let w = m[6] * x + m[7] * y + m[8];
+        [
+            (m[0] * x + m[1] * y + m[2]) / w,
+            (m[3] * x + m[4] * y + m[5]) / w,
+        ]
+    }
+}
+
+/// Are three of the points on one line
+fn has_collinear(points: [[f32; 2]; 4]) -> bool {
+    [[0, 1, 2], [0, 1, 3], [0, 2, 3], [1, 2, 3]]
+        .iter()
+        .any(|&[a, b, c]| {
+            let (a, b, c) = (points[a], points[b], points[c]);
+            let (ab, ac) = ([b[0] - a[0], b[1] - a[1]], [c[0] - a[0], c[1] - a[1]]);
+            let cross = ab[0] * ac[1] - ab[1] * ac[0];
+            cross.abs() <= 1e-6 * ab[0].hypot(ab[1]) * ac[0].hypot(ac[1])
+        })
+}
+
+/// Resample the quadrilateral with the given corners into an upright `width` by `height` image
+///
+/// The corners are clockwise from the top left, and are mapped to the centres of the corner
+/// pixels of the output. Positions outside the image read the nearest pixel inside it.
+///
+/// Returns `None` if three of the corners are on one line.
+///
+/// # Panics
+///
+/// If the output or input image has no pixels.
+#[must_use]
+pub fn warp_perspective(
+    img: &RgbaImage,
+    corners: [[f32; 2]; 4],
+    width: u32,
+    height: u32,
+    interpolation: Interpolation,
+) -> Option<RgbaImage> {
+    #[cfg(target_arch = "wasm32")]
+    let _timer = performance::Timer::new("warp_perspective");
+    assert!(width > 0 && height > 0, "output must have pixels");
+    assert!(
+        img.width() > 0 && img.height() > 0,
+        "input must have pixels"
+    );
+
+    #[allow(clippy::cast_precision_loss)]
+    let (right, bottom) = ((width - 1) as f32, (height - 1) as f32);
+    let homography = Homography::from_points(
+        [[0.0, 0.0], [right, 0.0], [right, bottom], [0.0, bottom]],
+        corners,
+    )?;
+    Some(RgbaImage::from_fn(width, height, |x, y| {
+        #[allow(clippy::cast_precision_loss)]
+        let position = homography.apply([x as f32, y as f32]);
+        match interpolation {
+            Interpolation::Bilinear => bilinear(img, position),
+            Interpolation::Bicubic => bicubic(img, position),
+        }
+    }))
+}
+
+/// Pixel at (x, y), or the nearest pixel in the image to it
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn clamped_pixel(img: &RgbaImage, x: i64, y: i64) -> [f32; 4] {
+    let x = x.clamp(0, i64::from(img.width()) - 1) as u32;
+    let y = y.clamp(0, i64::from(img.height()) - 1) as u32;
+    let Rgba(pixel) = *img.get_pixel(x, y);
+    [
+        f32::from(pixel[0]),
+        f32::from(pixel[1]),
+        f32::from(pixel[2]),
+        f32::from(pixel[3]),
+    ]
+}
+
+/// Round each channel to the nearest byte
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn to_pixel(channels: [f32; 4]) -> Rgba<u8> {
+    let byte = |channel: f32| channel.round().clamp(0.0, 255.0) as u8;
+    Rgba([
+        byte(channels[0]),
+        byte(channels[1]),
+        byte(channels[2]),
+        byte(channels[3]),
+    ])
+}
+
+/// Whole pixel at or before a position and the fraction of the way to the next
+#[allow(clippy::cast_possible_truncation)]
+fn split(position: f32) -> (i64, f32) {
+    let floor = position.floor();
+    (floor as i64, position - floor)
+}
+
+/// Weighted average of the four pixels around a position
+fn bilinear(img: &RgbaImage, [x, y]: [f32; 2]) -> Rgba<u8> {
+    let ((x, fx), (y, fy)) = (split(x), split(y));
+    let mut channels = [0.0; 4];
+    for (dy, wy) in [(0, 1.0 - fy), (1, fy)] {
+        for (dx, wx) in [(0, 1.0 - fx), (1, fx)] {
+            let pixel = clamped_pixel(img, x + dx, y + dy);
+            for (channel, value) in channels.iter_mut().zip(&pixel) {
+                *channel += wx * wy * value;
+            }
+        }
+    }
+    to_pixel(channels)
+}
+
+/// Catmull-Rom weights of the four pixels from one before a position to two after
+fn cubic_weights(t: f32) -> [f32; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [
+        0.5 * (-t3 + 2.0 * t2 - t),
+        0.5 * (3.0 * t3 - 5.0 * t2 + 2.0),
+        0.5 * (-3.0 * t3 + 4.0 * t2 + t),
+        0.5 * (t3 - t2),
+    ]
+}
+
+/// Catmull-Rom interpolation of the sixteen pixels around a position
+fn bicubic(img: &RgbaImage, [x, y]: [f32; 2]) -> Rgba<u8> {
+    let ((x, fx), (y, fy)) = (split(x), split(y));
+    let (wx, wy) = (cubic_weights(fx), cubic_weights(fy));
+    let mut channels = [0.0; 4];
+    for (dy, wy) in (-1..=2).zip(&wy) {
+        for (dx, wx) in (-1..=2).zip(&wx) {
+            let pixel = clamped_pixel(img, x + dx, y + dy);
+            for (channel, value) in channels.iter_mut().zip(&pixel) {
+                *channel += wx * wy * value;
+            }
+        }
+    }
+    to_pixel(channels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{warp_perspective, Homography, Interpolation};
+    use image::{Rgba, RgbaImage};
+
+    fn near(a: [f32; 2], b: [f32; 2]) -> bool {
+        (a[0] - b[0]).abs() < 1e-3 && (a[1] - b[1]).abs() < 1e-3
+    }
+
+    #[test]
+    fn test_homography() {
+        let square = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        let identity = Homography::from_points(square, square).unwrap();
+        assert!(near(identity.apply([0.3, 7.0]), [0.3, 7.0]));
+
+        let quadrilateral = [[10.0, 12.0], [90.0, 5.0], [80.0, 70.0], [20.0, 60.0]];
+        let homography = Homography::from_points(square, quadrilateral).unwrap();
+        for (&from, &to) in square.iter().zip(&quadrilateral) {
+            assert!(
+                near(homography.apply(from), to),
+                "{:?}",
+                homography.apply(from)
+            );
+        }
+        // straight lines stay straight
+        let [x, y] = homography.apply([0.5, 0.0]);
+        let t = (x - 10.0) / 80.0;
+        assert!((y - (12.0 - 7.0 * t)).abs() < 1e-3);
+
+        // three corners on one line
+        let line = [[0.0, 0.0], [1.0, 1.0], [2.0, 2.0], [0.0, 5.0]];
+        assert_eq!(Homography::from_points(square, line), None);
+    }
+
+    #[test]
+    fn test_warp_crop() {
+        let img = RgbaImage::from_fn(40, 30, |x, y| Rgba([x as u8 * 6, y as u8 * 8, 100, 255]));
+        // corners on pixel centres of an axis aligned rectangle copy it exactly
+        let corners = [[5.0, 4.0], [24.0, 4.0], [24.0, 19.0], [5.0, 19.0]];
+        for &interpolation in &[Interpolation::Bilinear, Interpolation::Bicubic] {
+            let warped = warp_perspective(&img, corners, 20, 16, interpolation).unwrap();
+            assert_eq!(warped.dimensions(), (20, 16));
+            for (x, y, pixel) in warped.enumerate_pixels() {
+                assert_eq!(pixel, img.get_pixel(x + 5, y + 4), "{:?}", interpolation);
+            }
+        }
+
+        // half way between pixels
+        let corners = [[5.5, 4.0], [24.5, 4.0], [24.5, 19.0], [5.5, 19.0]];
+        let warped = warp_perspective(&img, corners, 20, 16, Interpolation::Bilinear).unwrap();
+        assert_eq!(warped.get_pixel(0, 0), &Rgba([33, 32, 100, 255]));
+
+        // upside down
+        let corners = [[24.0, 19.0], [5.0, 19.0], [5.0, 4.0], [24.0, 4.0]];
+        let warped = warp_perspective(&img, corners, 20, 16, Interpolation::Bicubic).unwrap();
+        assert_eq!(warped.get_pixel(0, 0), img.get_pixel(24, 19));
+    }
+
+    #[test]
+    fn test_warp_perspective() {
+        // light quadrilateral on a dark background, seen at an angle
+        let corners = [[30.0, 10.0], [100.0, 25.0], [90.0, 110.0], [15.0, 95.0]];
+        let inside = |x: f32, y: f32| {
+            (0..4).all(|i| {
+                let ([ax, ay], [bx, by]) = (corners[i], corners[(i + 1) % 4]);
+                (bx - ax) * (y - ay) - (by - ay) * (x - ax) >= 0.0
+            })
+        };
+        let img = RgbaImage::from_fn(120, 120, |x, y| {
+            if inside(x as f32, y as f32) {
+                Rgba([230, 220, 200, 255])
+            } else {
+                Rgba([20, 40, 30, 255])
+            }
+        });
+
+        for &interpolation in &[Interpolation::Bilinear, Interpolation::Bicubic] {
+            let warped = warp_perspective(&img, corners, 50, 70, interpolation).unwrap();
+            // everything away from the outline is the inside of the quadrilateral
+            for (x, y, pixel) in warped.enumerate_pixels() {
+                if (2..48).contains(&x) && (2..68).contains(&y) {
+                    assert_eq!(pixel, &Rgba([230, 220, 200, 255]), "{} {}", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_warp_outside() {
+        let img = RgbaImage::from_pixel(10, 10, Rgba([1, 2, 3, 4]));
+        // positions outside the image read the edge pixels
+        let corners = [[-20.0, -20.0], [30.0, -20.0], [30.0, 30.0], [-20.0, 30.0]];
+        for &interpolation in &[Interpolation::Bilinear, Interpolation::Bicubic] {
+            let warped = warp_perspective(&img, corners, 7, 9, interpolation).unwrap();
+            assert!(warped.pixels().all(|pixel| pixel == &Rgba([1, 2, 3, 4])));
+        }
+        let line = [[0.0, 0.0], [1.0, 1.0], [2.0, 2.0], [3.0, 3.0]];
+        assert_eq!(
+            warp_perspective(&img, line, 7, 9, Interpolation::Bilinear),
+            None
+        );
+    }
+}
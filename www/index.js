@@ -1,4 +1,4 @@
-import init, { Detector } from "./pack_stack.js";
+import init, { Detector, Format } from "./pack_stack.js";
 
 const video = document.getElementById("video");
 const output = document.getElementById("output");
@@ -6,12 +6,17 @@ const context = output.getContext("2d");
 
 let detector;
 
+// width in pixels of captured cards
+const CARD_WIDTH = 250;
+
 const loadData = () => {
   console.log(video.videoHeight, video.videoWidth);
   output.width = video.videoWidth;
   output.height = video.videoHeight;
 
-  detector = Detector.new(video.videoWidth, video.videoHeight, true);
+  detector = Detector.with_format(video.videoWidth, video.videoHeight, Format.Poker, false);
+  // centre the guide, webcams are usually landscape
+  detector.place(0.5, 0.5);
 
   window.requestAnimationFrame(tick);
 };
@@ -23,13 +28,17 @@ const tick = () => {
   const imageData = context.getImageData(0, 0, video.videoWidth, video.videoHeight);
   const data = detector.detect(imageData.data);
   if (detector.boundary_match()) {
-    console.log("CAPTURE");
-    let capture = document.createElement("canvas");
-    capture.width = output.width;
-    capture.height = output.height;
-    let captureCtx = capture.getContext("2d");
-    captureCtx.putImageData(imageData, 0, 0);
-    document.getElementById("captures").appendChild(capture);
+    console.log("CAPTURE", detector.low_threshold(), detector.high_threshold());
+    const card = detector.extract(imageData.data, CARD_WIDTH);
+    // nothing to capture if the card could not be cut out
+    if (card.length > 0) {
+      let capture = document.createElement("canvas");
+      capture.width = CARD_WIDTH;
+      capture.height = card.length / 4 / CARD_WIDTH;
+      let captureCtx = capture.getContext("2d");
+      captureCtx.putImageData(new ImageData(card, capture.width, capture.height), 0, 0);
+      document.getElementById("captures").appendChild(capture);
+    }
   }
   context.putImageData(new ImageData(data, video.videoWidth, video.videoHeight), 0, 0);
   window.requestAnimationFrame(tick);